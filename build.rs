@@ -0,0 +1,58 @@
+//! Finds the versions of tonic-build and prost-build locked in the `Cargo.lock` file of the
+//! build, whose exact versions are part of the hash deciding whether generated code is up to
+//! date. This is best-effort: if the lock file can't be found or read, nothing is emitted and
+//! the version requirements are used instead.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The lock file of the build is next to the target directory, which holds OUT_DIR.
+    let lock = match env::var_os("OUT_DIR")
+        .map(PathBuf::from)
+        .and_then(|out_dir| {
+            out_dir
+                .ancestors()
+                .map(|dir| dir.join("Cargo.lock"))
+                .find(|lock| lock.is_file())
+        }) {
+        Some(lock) => lock,
+        None => return,
+    };
+    println!("cargo:rerun-if-changed={}", lock.display());
+
+    let lock = match fs::read_to_string(lock) {
+        Ok(lock) => lock,
+        Err(_) => return,
+    };
+
+    // The lock file may hold several versions of a package; the crate picks the one matching
+    // its requirement.
+    for (package, var) in [
+        ("tonic-build", "TONIC_BUILD"),
+        ("prost-build", "PROST_BUILD"),
+    ] {
+        println!(
+            "cargo:rustc-env=GRPC_BUILD_LOCKED_{}={}",
+            var,
+            locked_versions(&lock, package).join(" ")
+        );
+    }
+}
+
+/// The versions of `name` in the lock file `lock`.
+fn locked_versions(lock: &str, name: &str) -> Vec<String> {
+    let name_line = format!("name = \"{}\"", name);
+    lock.split("[[package]]")
+        .filter(|package| package.lines().any(|line| line.trim() == name_line))
+        .filter_map(|package| {
+            let version = package
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("version = \""))?;
+            Some(version.trim_end_matches('"').to_string())
+        })
+        .collect()
+}
@@ -0,0 +1,849 @@
+use crate::cache::Cache;
+use crate::check::CheckReport;
+use crate::crate_gen::{uses_prost_types, Crate, LibRenderer, SERDE_ATTRIBUTE};
+use crate::discovery::{discover, Discovery};
+use crate::dry_run::DryRun;
+use crate::features::{PackageFeatures, ServiceFeatures};
+use crate::format::Formatter;
+use crate::imports::{read_protos, ProtoSource};
+use crate::layout::{Layout, ModRenderer};
+use crate::manifest::{InputHasher, Manifest};
+use crate::module_tree::{
+    generated_files, is_module_ident, is_package_module, lay_out, module_ident, ModuleTree,
+    Packageless, PACKAGELESS_FILE,
+};
+use crate::proto_file;
+use crate::tonic_builder::{compile, compile_includes};
+use crate::BuildError;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// Configures and runs the compilation of a directory of protobuf files.
+///
+/// ```no_run
+/// grpc_build::Builder::new()
+///     .in_dir("protos")
+///     .out_dir("src/protogen")
+///     .build_client(true)
+///     .build_server(true)
+///     .force(true)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    tonic: tonic_build::Builder,
+    in_dirs: Vec<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    globs: Vec<String>,
+    respect_ignore_files: bool,
+    out_dir: Option<PathBuf>,
+    force: bool,
+    always_regenerate: bool,
+    formatter: Formatter,
+    renderer: Arc<dyn ModRenderer>,
+    packageless: Option<Packageless>,
+    standalone_crate: Option<Crate>,
+    service_features: Option<ServiceFeatures>,
+    package_features: Option<PackageFeatures>,
+    emit_rerun_if_changed: Option<bool>,
+    cache: Option<Cache>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    /// Creates a builder that generates neither the gRPC client nor the server.
+    pub fn new() -> Self {
+        Self {
+            // Formatting is done by `Builder::formatter` once all the files are laid out.
+            tonic: tonic_build::configure()
+                .build_client(false)
+                .build_server(false)
+                .format(false),
+            in_dirs: Vec::new(),
+            include_dirs: Vec::new(),
+            globs: Vec::new(),
+            respect_ignore_files: false,
+            out_dir: None,
+            force: false,
+            always_regenerate: false,
+            formatter: Formatter::default(),
+            renderer: Arc::new(Layout::default()),
+            packageless: None,
+            standalone_crate: None,
+            service_features: None,
+            package_features: None,
+            emit_rerun_if_changed: None,
+            cache: None,
+        }
+    }
+
+    /// Add a directory whose protobuf files are compiled.
+    ///
+    /// Can be called multiple times to compile several source roots together.
+    pub fn in_dir(mut self, in_dir: impl AsRef<Path>) -> Self {
+        self.in_dirs.push(in_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add an import-only root, passed to protoc as `-I` but not compiled.
+    ///
+    /// Input directories that do not live under any include directory have their parent
+    /// directory added to the include path, so `import` statements are resolved relative to it.
+    pub fn include_dir(mut self, include_dir: impl AsRef<Path>) -> Self {
+        self.include_dirs.push(include_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a glob pattern filtering which protobuf files are compiled.
+    ///
+    /// Patterns are matched against paths relative to their input directory, so
+    /// `**/internal/**` only compiles files inside an `internal` directory. Patterns
+    /// prefixed with `!` exclude files instead, e.g. `!**/testdata/**`. Exclusions take
+    /// precedence over inclusions, and every file is compiled if no include pattern is given.
+    pub fn glob(mut self, pattern: impl Into<String>) -> Self {
+        self.globs.push(pattern.into());
+        self
+    }
+
+    /// Skip the protobuf files matched by `.gitignore` and `.ignore` files.
+    pub fn respect_ignore_files(mut self, enable: bool) -> Self {
+        self.respect_ignore_files = enable;
+        self
+    }
+
+    /// The directory the generated code and the `mod.rs` file are written to.
+    pub fn out_dir(mut self, out_dir: impl AsRef<Path>) -> Self {
+        self.out_dir = Some(out_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Enable or disable gRPC client code generation.
+    pub fn build_client(mut self, enable: bool) -> Self {
+        self.tonic = self.tonic.build_client(enable);
+        self
+    }
+
+    /// Enable or disable gRPC server code generation.
+    pub fn build_server(mut self, enable: bool) -> Self {
+        self.tonic = self.tonic.build_server(enable);
+        self
+    }
+
+    /// Write to an output directory that was not generated by grpc-build, and overwrite
+    /// generated files that were edited since the last run.
+    ///
+    /// Without it, the build fails instead of overwriting any file it does not own.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Regenerate the code even if the inputs did not change since the last run.
+    ///
+    /// By default, the build does nothing when the manifest of the output directory records the
+    /// same inputs and none of the generated files were modified.
+    pub fn always_regenerate(mut self, enable: bool) -> Self {
+        self.always_regenerate = enable;
+        self
+    }
+
+    /// Print `cargo:rerun-if-changed` directives for every compiled protobuf file, every file
+    /// they import and every input and include directory, plus `cargo:rerun-if-env-changed` for
+    /// `PROTOC` and `PROTOC_INCLUDE`.
+    ///
+    /// By default they are printed when running inside a build script, i.e. when both the
+    /// `OUT_DIR` and `CARGO` environment variables are set.
+    pub fn emit_rerun_if_changed(mut self, enable: bool) -> Self {
+        self.emit_rerun_if_changed = Some(enable);
+        self
+    }
+
+    /// Serve identical compile requests from a [`Cache`] shared between crates and runs,
+    /// located in [`Cache::default_dir`].
+    pub fn cache(mut self, enable: bool) -> Self {
+        self.cache = if enable { Some(Cache::default()) } else { None };
+        self
+    }
+
+    /// Serve identical compile requests from a [`Cache`] located in `cache_dir`.
+    pub fn cache_dir(mut self, cache_dir: impl AsRef<Path>) -> Self {
+        self.cache = Some(Cache::new(cache_dir));
+        self
+    }
+
+    /// How the generated files are formatted. Defaults to running `rustfmt`.
+    pub fn formatter(mut self, formatter: Formatter) -> Self {
+        self.formatter = formatter;
+        self
+    }
+
+    /// How the generated `mod.rs` file pulls in the generated files. Defaults to
+    /// [`Layout::Path`]; use [`Layout::Include`] when generating into `OUT_DIR`, or
+    /// [`Layout::Directory`] for a module file in each directory.
+    pub fn layout(self, layout: Layout) -> Self {
+        self.renderer(layout)
+    }
+
+    /// Write the modules declaring the generated files with a custom [`ModRenderer`].
+    pub fn renderer(mut self, renderer: impl ModRenderer + 'static) -> Self {
+        self.renderer = Arc::new(renderer);
+        self
+    }
+
+    /// Where the items of the protobuf files without a `package` declaration go.
+    ///
+    /// Compiling such files fails unless this is set.
+    pub fn packageless(mut self, packageless: Packageless) -> Self {
+        self.packageless = Some(packageless);
+        self
+    }
+
+    /// Generate a standalone crate in the output directory: a `Cargo.toml` file depending on
+    /// the versions of prost and tonic the code is generated for, and the generated code in
+    /// `src`, with the root module in `src/lib.rs`.
+    pub fn standalone_crate(mut self, standalone_crate: Crate) -> Self {
+        self.standalone_crate = Some(standalone_crate);
+        self
+    }
+
+    /// Generate both the gRPC clients and servers, each behind a cargo feature, instead of
+    /// following [`Builder::build_client`] and [`Builder::build_server`].
+    pub fn service_features(mut self, service_features: ServiceFeatures) -> Self {
+        self.service_features = Some(service_features);
+        self
+    }
+
+    /// Compile the modules of the generated packages behind cargo features, each enabling the
+    /// features of the packages it imports.
+    pub fn package_features(mut self, package_features: PackageFeatures) -> Self {
+        self.package_features = Some(package_features);
+        self
+    }
+
+    /// Declare externally provided Protobuf package or type.
+    ///
+    /// Passed directly to `tonic_build::Builder::extern_path`.
+    pub fn extern_path(mut self, proto_path: impl AsRef<str>, rust_path: impl AsRef<str>) -> Self {
+        self.tonic = self.tonic.extern_path(proto_path, rust_path);
+        self
+    }
+
+    /// Add additional attribute to matched messages, enums, and one-offs.
+    ///
+    /// Passed directly to `tonic_build::Builder::type_attribute`.
+    pub fn type_attribute(mut self, path: impl AsRef<str>, attribute: impl AsRef<str>) -> Self {
+        self.tonic = self.tonic.type_attribute(path, attribute);
+        self
+    }
+
+    /// Add additional attribute to matched fields.
+    ///
+    /// Passed directly to `tonic_build::Builder::field_attribute`.
+    pub fn field_attribute(mut self, path: impl AsRef<str>, attribute: impl AsRef<str>) -> Self {
+        self.tonic = self.tonic.field_attribute(path, attribute);
+        self
+    }
+
+    /// Customise the underlying [`tonic_build::Builder`] directly.
+    ///
+    /// The output directory is always overridden by [`Builder::out_dir`].
+    pub fn tonic_config(
+        mut self,
+        f: impl FnOnce(tonic_build::Builder) -> tonic_build::Builder,
+    ) -> Self {
+        self.tonic = f(self.tonic);
+        self
+    }
+
+    /// Finds the protobuf files that will be compiled, and the ones that are filtered out.
+    pub fn discover(&self) -> Result<Discovery, BuildError> {
+        discover(&self.in_dirs, &self.globs, self.respect_ignore_files)
+    }
+
+    /// Compiles the protobuf files and generates the `mod.rs` file.
+    ///
+    /// The code is generated in a temporary sibling of the output directory, and only moved
+    /// into the output directory once every step succeeded, so a failure to compile, lay out or
+    /// format the code leaves the previous contents of the output directory untouched. The
+    /// files are then moved one by one: if that fails partway, the output directory holds a mix
+    /// of old and new files, which the next run completes as the manifest is moved last.
+    ///
+    /// A manifest recording the hashes of the inputs and of the generated files is written to
+    /// the output directory. If the inputs did not change since the last run, nothing is
+    /// regenerated; otherwise only the files whose contents changed are rewritten, and the
+    /// files that are no longer generated are removed. Files that were not generated by
+    /// grpc-build, such as hand-written modules, are never removed.
+    pub fn build(self) -> Result<(), BuildError> {
+        let out_dir = self.checked_out_dir()?;
+        let discovery = self.discover()?;
+
+        let sources = self.read_protos(&discovery)?;
+
+        let in_build_script = env::var_os("OUT_DIR").is_some() && env::var_os("CARGO").is_some();
+        if self.emit_rerun_if_changed.unwrap_or(in_build_script) {
+            self.print_rerun_if_changed(&discovery, &sources);
+        }
+
+        let previous = if out_dir.exists() {
+            Manifest::read(&out_dir).map_err(|source| BuildError::Io {
+                path: out_dir.clone(),
+                source,
+            })?
+        } else {
+            None
+        };
+
+        if out_dir.exists() && previous.is_none() && !self.force {
+            return Err(BuildError::OutputDirectoryExistsError(
+                out_dir.display().to_string(),
+            ));
+        }
+
+        let input_hash = self.input_hash(&discovery, &sources)?;
+
+        if let Some(previous) = previous.as_ref().filter(|_| !self.always_regenerate) {
+            let intact = previous
+                .is_intact(&out_dir)
+                .map_err(|source| BuildError::Io {
+                    path: out_dir.clone(),
+                    source,
+                })?;
+            if intact && previous.input_hash == input_hash {
+                return Ok(());
+            }
+        }
+
+        let staging = staging_dir(&out_dir).map_err(|source| BuildError::Io {
+            path: out_dir.clone(),
+            source,
+        })?;
+        let generated_dir = staging.path().join("generated");
+        fs::create_dir(&generated_dir).map_err(|source| BuildError::Io {
+            path: generated_dir.clone(),
+            source,
+        })?;
+
+        let force = self.force;
+        let manifest =
+            self.generate_cached(&discovery, &sources, input_hash, &generated_dir, &out_dir)?;
+
+        let out_dir_error = |source| BuildError::Io {
+            path: out_dir.clone(),
+            source,
+        };
+
+        if !out_dir.exists() {
+            return fs::rename(&generated_dir, &out_dir).map_err(out_dir_error);
+        }
+
+        // Without a manifest, every existing file is treated as user-owned.
+        let previous = previous.unwrap_or_else(|| Manifest::empty(String::new()));
+
+        if !force {
+            let modified = manifest
+                .conflicts(&out_dir, &previous)
+                .map_err(out_dir_error)?;
+            if !modified.is_empty() {
+                return Err(BuildError::ModifiedFilesError(
+                    modified.iter().map(|path| out_dir.join(path)).collect(),
+                ));
+            }
+        }
+
+        manifest
+            .sync(&generated_dir, &out_dir, &previous)
+            .map_err(out_dir_error)
+    }
+
+    /// Runs the whole pipeline in a temporary directory and compares the result with the
+    /// output directory, without modifying it.
+    ///
+    /// Files the output directory does not have, files whose contents differ, and generated
+    /// files that would be removed are reported as out of date. Files that were not generated
+    /// by grpc-build are ignored.
+    pub fn check(self) -> Result<CheckReport, BuildError> {
+        let out_dir = self.checked_out_dir()?;
+        let discovery = self.discover()?;
+        let sources = self.read_protos(&discovery)?;
+        let input_hash = self.input_hash(&discovery, &sources)?;
+
+        let out_dir_error = |source| BuildError::Io {
+            path: out_dir.clone(),
+            source,
+        };
+        let previous = if out_dir.exists() {
+            Manifest::read(&out_dir).map_err(out_dir_error)?
+        } else {
+            None
+        };
+
+        let staging = tempfile::tempdir().map_err(|source| BuildError::Io {
+            path: env::temp_dir(),
+            source,
+        })?;
+        let manifest =
+            self.generate_cached(&discovery, &sources, input_hash, staging.path(), &out_dir)?;
+
+        CheckReport::compare(staging.path(), &manifest, &out_dir, previous.as_ref())
+            .map_err(out_dir_error)
+    }
+
+    /// Runs discovery, protoc and the module layout in a temporary directory, and reports the
+    /// files that would be written to the output directory, without modifying it.
+    pub fn dry_run(self) -> Result<DryRun, BuildError> {
+        let out_dir = self.checked_out_dir()?;
+        let discovery = self.discover()?;
+        let sources = self.read_protos(&discovery)?;
+
+        let staging = tempfile::tempdir().map_err(|source| BuildError::Io {
+            path: env::temp_dir(),
+            source,
+        })?;
+        let protos = discovery.protos.clone();
+        let input_hash = self.input_hash(&discovery, &sources)?;
+        let renderer = self.root_renderer();
+        let generated_dir = self.code_dir(staging.path());
+        let formatter = self.formatter.clone();
+        let module_tree = self.generate(&discovery, &sources, staging.path())?;
+        format(&formatter, staging.path(), &out_dir)?;
+
+        let staging_error = |source| BuildError::Io {
+            path: staging.path().to_path_buf(),
+            source,
+        };
+        Manifest::for_dir(staging.path(), input_hash)
+            .and_then(|manifest| manifest.write(staging.path()))
+            .map_err(staging_error)?;
+
+        DryRun::read(
+            protos,
+            module_tree,
+            &*renderer,
+            staging.path(),
+            &generated_dir,
+            &out_dir,
+        )
+        .map_err(staging_error)
+    }
+
+    /// The directory the code is generated in: `out_dir`, or its `src` directory for a
+    /// standalone crate.
+    fn code_dir(&self, out_dir: &Path) -> PathBuf {
+        match &self.standalone_crate {
+            Some(_) => out_dir.join("src"),
+            None => out_dir.to_path_buf(),
+        }
+    }
+
+    /// The renderer of the root module, written to `lib.rs` for a standalone crate.
+    fn root_renderer(&self) -> Arc<dyn ModRenderer> {
+        match &self.standalone_crate {
+            Some(_) => Arc::new(LibRenderer(self.renderer.clone())),
+            None => self.renderer.clone(),
+        }
+    }
+
+    /// The output directory, after checking that both an input and an output directory were
+    /// specified, and that the name of the standalone crate, if any, is valid.
+    fn checked_out_dir(&self) -> Result<PathBuf, BuildError> {
+        if self.in_dirs.is_empty() {
+            return Err(BuildError::MissingDirectoryError(String::from("input")));
+        }
+
+        if let Some(standalone_crate) = &self.standalone_crate {
+            standalone_crate.check_name()?;
+        }
+
+        self.out_dir
+            .clone()
+            .ok_or_else(|| BuildError::MissingDirectoryError(String::from("output")))
+    }
+
+    /// Generates and formats the code in `generated_dir`, going through the cache if one is
+    /// configured, and writes the manifest describing it. The code is meant for `out_dir`, which
+    /// errors are reported against.
+    fn generate_cached(
+        self,
+        discovery: &Discovery,
+        sources: &[ProtoSource],
+        input_hash: String,
+        generated_dir: &Path,
+        out_dir: &Path,
+    ) -> Result<Manifest, BuildError> {
+        let formatter = self.formatter.clone();
+        match self.cache.clone() {
+            Some(cache) => {
+                let cache_error = |source| BuildError::Io {
+                    path: cache.dir().to_path_buf(),
+                    source,
+                };
+
+                // The cache holds unformatted code, as the output of the formatter also depends
+                // on its version and configuration files, which are not part of the key.
+                if !cache
+                    .restore(&input_hash, generated_dir)
+                    .map_err(cache_error)?
+                {
+                    self.generate(discovery, sources, generated_dir)?;
+                    cache
+                        .store(&input_hash, generated_dir)
+                        .map_err(cache_error)?;
+                }
+            }
+            None => {
+                self.generate(discovery, sources, generated_dir)?;
+            }
+        }
+        format(&formatter, generated_dir, out_dir)?;
+
+        Manifest::for_dir(generated_dir, input_hash)
+            .and_then(|manifest| manifest.write(generated_dir).map(|_| manifest))
+            .map_err(|source| BuildError::Io {
+                path: generated_dir.to_path_buf(),
+                source,
+            })
+    }
+
+    /// Hashes the compiled protobuf files, the files they import and every option influencing
+    /// the generated code.
+    fn input_hash(
+        &self,
+        discovery: &Discovery,
+        sources: &[ProtoSource],
+    ) -> Result<String, BuildError> {
+        let mut hasher = InputHasher::new();
+        hasher.field("tonic", format!("{:?}", self.tonic));
+        hasher.field("formatter", format!("{:?}", self.formatter));
+        hasher.field("renderer", format!("{:?}", self.renderer));
+        hasher.field("packageless", format!("{:?}", self.packageless));
+        hasher.field("crate", format!("{:?}", self.standalone_crate));
+        hasher.field("service features", format!("{:?}", self.service_features));
+        hasher.field("package features", format!("{:?}", self.package_features));
+
+        for in_dir in &self.in_dirs {
+            let name = in_dir.file_name().unwrap_or_default();
+            hasher.field("input", name.to_string_lossy().as_bytes());
+
+            for proto in discovery
+                .protos
+                .iter()
+                .filter(|proto| proto.starts_with(in_dir))
+            {
+                hasher
+                    .file(in_dir, proto)
+                    .map_err(|source| BuildError::Io {
+                        path: proto.clone(),
+                        source,
+                    })?;
+            }
+        }
+
+        // Imports are hashed by the name they are imported with, as they are found through
+        // any include root, including the implicit parent of an input directory.
+        for import in sources.iter().filter(|source| !source.compiled) {
+            hasher.field("import", &import.name);
+            hasher.field("contents", &import.source);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Reads the compiled protobuf files and the files they import.
+    fn read_protos(&self, discovery: &Discovery) -> Result<Vec<ProtoSource>, BuildError> {
+        read_protos(
+            &discovery.protos,
+            &compile_includes(&self.in_dirs, &self.include_dirs),
+        )
+    }
+
+    /// Prints the cargo directives re-running the build script when an input changes.
+    ///
+    /// Imported files are listed one by one rather than through their include root, as the
+    /// implicit root of an input directory is its parent, which may be the whole crate.
+    fn print_rerun_if_changed(&self, discovery: &Discovery, sources: &[ProtoSource]) {
+        let imported = sources
+            .iter()
+            .filter(|source| !source.compiled)
+            .map(|source| &source.path);
+        for path in discovery
+            .protos
+            .iter()
+            .chain(imported)
+            .chain(&self.in_dirs)
+            .chain(&self.include_dirs)
+        {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+
+        for var in &["PROTOC", "PROTOC_INCLUDE"] {
+            println!("cargo:rerun-if-env-changed={}", var);
+        }
+    }
+
+    /// Runs protoc, lays out the generated files and writes the `mod.rs` file in `out_dir`.
+    /// Returns the module tree of the generated files.
+    ///
+    /// `sources` are the compiled protobuf files and the files they import, which prost
+    /// generates code for as well.
+    fn generate(
+        self,
+        discovery: &Discovery,
+        sources: &[ProtoSource],
+        out_dir: &Path,
+    ) -> Result<ModuleTree, BuildError> {
+        let code_dir = self.code_dir(out_dir);
+        let renderer = self.root_renderer();
+        let code_dir_error = |source| BuildError::Io {
+            path: code_dir.clone(),
+            source,
+        };
+
+        let mut tonic = self.tonic;
+        if let Some(service_features) = &self.service_features {
+            tonic = service_features.apply(tonic);
+        }
+        if self
+            .standalone_crate
+            .as_ref()
+            .is_some_and(|standalone_crate| standalone_crate.has_serde())
+        {
+            tonic = tonic.type_attribute(".", SERDE_ATTRIBUTE);
+        }
+        fs::create_dir_all(&code_dir).map_err(code_dir_error)?;
+        compile(
+            &discovery.protos,
+            &self.in_dirs,
+            &self.include_dirs,
+            &code_dir,
+            tonic,
+        )
+        .map_err(BuildError::Protoc)?;
+
+        let file_names = generated_files(&code_dir).map_err(BuildError::Layout)?;
+        if file_names.iter().any(|name| name == PACKAGELESS_FILE) {
+            check_packageless(self.packageless.as_ref(), sources, &file_names)?;
+        }
+
+        let mut tree = ModuleTree::new(&file_names, self.packageless.as_ref());
+        let mut features = BTreeMap::new();
+        if let Some(service_features) = &self.service_features {
+            service_features.declare(&mut features);
+        }
+        if let Some(package_features) = &self.package_features {
+            package_features.apply(&mut tree, sources, &mut features)?;
+        }
+        lay_out(&tree, &code_dir).map_err(BuildError::Layout)?;
+
+        renderer
+            .render(&tree, &code_dir)
+            .map_err(|source| BuildError::ModFileWrite {
+                path: code_dir.clone(),
+                source,
+            })?;
+
+        if let Some(standalone_crate) = &self.standalone_crate {
+            let prost_types = uses_prost_types(&code_dir).map_err(code_dir_error)?;
+            let manifest_path = out_dir.join("Cargo.toml");
+            let manifest = standalone_crate.manifest(prost_types, &features);
+            fs::write(&manifest_path, manifest).map_err(|source| BuildError::Io {
+                path: manifest_path,
+                source,
+            })?;
+        }
+
+        Ok(tree)
+    }
+}
+
+/// Formats the code generated in `generated_dir` with `formatter`, reporting errors against
+/// `out_dir`, which the code is meant for, as the scratch directory is gone by the time the
+/// error is seen.
+fn format(formatter: &Formatter, generated_dir: &Path, out_dir: &Path) -> Result<(), BuildError> {
+    formatter
+        .format_dir(generated_dir)
+        .map_err(|source| BuildError::Format {
+            path: out_dir.to_path_buf(),
+            source: source.relocate(generated_dir, out_dir),
+        })
+}
+
+/// Checks that the items of the protobuf files without a `package` declaration, compiled or
+/// imported along the files named `file_names`, can be placed according to `packageless`.
+fn check_packageless(
+    packageless: Option<&Packageless>,
+    sources: &[ProtoSource],
+    file_names: &[String],
+) -> Result<(), BuildError> {
+    let (packageless_sources, packaged): (Vec<_>, Vec<_>) = sources
+        .iter()
+        .partition(|source| proto_file::package(&source.source).is_none());
+    let packageless_protos: Vec<PathBuf> = packageless_sources
+        .iter()
+        .map(|source| source.path.clone())
+        .collect();
+
+    let module = match packageless {
+        Some(Packageless::Hoist) => return Ok(()),
+        Some(Packageless::Module(module)) => module,
+        None => return Err(BuildError::PackagelessProtos(packageless_protos)),
+    };
+
+    let module_error = |reason: &str, protos| BuildError::PackagelessModule {
+        module: module.clone(),
+        reason: String::from(reason),
+        protos,
+    };
+
+    if !is_module_ident(&module_ident(module)) {
+        return Err(module_error(
+            "which is not a valid module name",
+            packageless_protos,
+        ));
+    }
+
+    if is_package_module(file_names, module) {
+        return Err(module_error(
+            "which is also the name of a package",
+            packageless_protos,
+        ));
+    }
+
+    // prost refers to their items relative to the root module, so they have to be hoisted.
+    let imported: Vec<PathBuf> = packageless_sources
+        .into_iter()
+        .filter(|packageless| {
+            packaged
+                .iter()
+                .any(|source| source.imports().contains(&packageless.name))
+        })
+        .map(|packageless| packageless.path.clone())
+        .collect();
+    if !imported.is_empty() {
+        return Err(module_error(
+            "as packages import some of them and can only refer to them when hoisted",
+            imported,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Creates a temporary directory next to `out_dir`, so that renaming out of it stays on the
+/// same filesystem.
+fn staging_dir(out_dir: &Path) -> io::Result<TempDir> {
+    let parent = match out_dir.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+
+    let name = out_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    tempfile::Builder::new()
+        .prefix(&format!(".{}.grpc-build-", name))
+        .tempdir_in(parent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proto(name: &str, compiled: bool, source: &str) -> ProtoSource {
+        ProtoSource {
+            path: PathBuf::from(if compiled { "protos" } else { "vendor" }).join(name),
+            name: name.to_string(),
+            compiled,
+            source: source.to_string(),
+        }
+    }
+
+    fn packageless_error(
+        packageless: Option<&Packageless>,
+        sources: &[ProtoSource],
+        file_names: &[&str],
+    ) -> Option<String> {
+        let file_names: Vec<String> = file_names.iter().map(|name| name.to_string()).collect();
+        check_packageless(packageless, sources, &file_names)
+            .err()
+            .map(|error| error.to_string())
+    }
+
+    #[test]
+    fn imported_packageless_files_need_to_be_placed() {
+        let sources = [
+            proto(
+                "acme/a.proto",
+                true,
+                "package acme;\nimport \"money.proto\";",
+            ),
+            proto("money.proto", false, "message Money {}"),
+        ];
+        let file_names = ["_.rs", "acme.rs"];
+
+        let error = packageless_error(None, &sources, &file_names).unwrap();
+        assert!(error.ends_with(": vendor/money.proto"), "{}", error);
+
+        assert_eq!(
+            packageless_error(Some(&Packageless::Hoist), &sources, &file_names),
+            None
+        );
+
+        let types = Packageless::Module(String::from("types"));
+        let error = packageless_error(Some(&types), &sources, &file_names).unwrap();
+        assert!(
+            error.contains("as packages import some of them")
+                && error.ends_with(": vendor/money.proto"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn packageless_files_can_be_placed_under_a_module_unless_imported() {
+        let sources = [
+            proto("acme/a.proto", true, "package acme;"),
+            proto("common.proto", true, "import \"acme/a.proto\";"),
+        ];
+        let file_names = ["_.rs", "acme.rs"];
+
+        let types = Packageless::Module(String::from("types"));
+        assert_eq!(packageless_error(Some(&types), &sources, &file_names), None);
+
+        let acme = Packageless::Module(String::from("acme"));
+        let error = packageless_error(Some(&acme), &sources, &file_names).unwrap();
+        assert!(
+            error.contains("which is also the name of a package"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn packageless_modules_need_a_valid_name() {
+        let sources = [proto("common.proto", true, "message Common {}")];
+
+        for module in ["", "1types", "2"] {
+            let module = Packageless::Module(String::from(module));
+            let error = packageless_error(Some(&module), &sources, &["_.rs"]).unwrap();
+            assert!(
+                error.contains("which is not a valid module name"),
+                "{}",
+                error
+            );
+        }
+    }
+}
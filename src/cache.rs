@@ -0,0 +1,283 @@
+use crate::manifest::walk_files;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A directory of previously generated code, shared between crates and runs.
+///
+/// Entries are keyed by a hash of the protobuf files and of the code generation options, so a
+/// compile request identical to a previous one is served by copying its output instead of
+/// running protoc and tonic-build again.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// The number of entries in a [`Cache`] and their total size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size_bytes: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new(Self::default_dir())
+    }
+}
+
+impl Cache {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// `grpc-build-cache` inside the cargo target directory.
+    ///
+    /// The target directory is taken from `CARGO_TARGET_DIR` if set, then derived from
+    /// `OUT_DIR` when running inside a build script, and defaults to `target`.
+    pub fn default_dir() -> PathBuf {
+        default_dir(
+            env::var_os("CARGO_TARGET_DIR"),
+            env::var_os("OUT_DIR"),
+            env::var_os("TARGET"),
+        )
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn stats(&self) -> io::Result<CacheStats> {
+        let mut stats = CacheStats::default();
+
+        for entry in self.entries()? {
+            let mut files = vec![];
+            walk_files(&entry, &mut files)?;
+
+            stats.entries += 1;
+            for file in files {
+                stats.size_bytes += fs::metadata(file)?.len();
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Removes every entry of the cache.
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut dirs = vec![];
+        for entry in entries {
+            let entry = entry?;
+            let is_staging = entry.file_name().to_string_lossy().starts_with('.');
+            if entry.file_type()?.is_dir() && !is_staging {
+                dirs.push(entry.path());
+            }
+        }
+
+        Ok(dirs)
+    }
+
+    /// Copies the entry for `key` into the empty directory `dest`, returning whether there was
+    /// one.
+    pub(crate) fn restore(&self, key: &str, dest: &Path) -> io::Result<bool> {
+        let entry = self.dir.join(key);
+        if !entry.is_dir() {
+            return Ok(false);
+        }
+
+        copy_dir(&entry, dest)?;
+        Ok(true)
+    }
+
+    /// Stores a copy of `src` as the entry for `key`.
+    ///
+    /// The copy is made in a staging directory first, so concurrent builds never observe a
+    /// partially written entry.
+    pub(crate) fn store(&self, key: &str, src: &Path) -> io::Result<()> {
+        let entry = self.dir.join(key);
+        if entry.is_dir() {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let staging = tempfile::Builder::new()
+            .prefix(&format!(".{}-", key))
+            .tempdir_in(&self.dir)?;
+        copy_dir(src, staging.path())?;
+
+        match fs::rename(staging.path(), &entry) {
+            // Another build stored the same entry in the meantime.
+            Err(_) if entry.is_dir() => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// [`Cache::default_dir`], given the `CARGO_TARGET_DIR`, `OUT_DIR` and `TARGET` environment
+/// variables.
+fn default_dir(
+    cargo_target_dir: Option<OsString>,
+    out_dir: Option<OsString>,
+    target: Option<OsString>,
+) -> PathBuf {
+    let target_dir = cargo_target_dir
+        .map(PathBuf::from)
+        .or_else(|| {
+            // OUT_DIR is `<target>/[<triple>/]<profile>/build/<package>-<hash>/out`, with the
+            // triple of `TARGET` when building for an explicit `--target`.
+            let out_dir = PathBuf::from(out_dir?);
+            let build_dir = out_dir.ancestors().find(|dir| dir.ends_with("build"))?;
+            let target_dir = build_dir.parent()?.parent()?;
+            match target {
+                Some(target) if target_dir.file_name() == Some(&target) => target_dir.parent(),
+                _ => Some(target_dir),
+            }
+            .map(Path::to_path_buf)
+        })
+        .unwrap_or_else(|| PathBuf::from("target"));
+
+    target_dir.join("grpc-build-cache")
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    let mut files = vec![];
+    walk_files(src, &mut files)?;
+
+    for file in files {
+        let target = dest.join(file.strip_prefix(src).unwrap_or(&file));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&file, target)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    #[test]
+    fn stored_entries_are_restored() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(cache_dir.path().join("cache"));
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(
+            generated_dir.path(),
+            &[("mod.rs", "pub mod acme;"), ("acme/acme.rs", "// acme")],
+        );
+
+        let restored_dir = tempfile::tempdir().unwrap();
+        assert!(!cache.restore("key", restored_dir.path()).unwrap());
+
+        cache.store("key", generated_dir.path()).unwrap();
+        assert!(cache.restore("key", restored_dir.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(restored_dir.path().join("mod.rs")).unwrap(),
+            "pub mod acme;"
+        );
+        assert_eq!(
+            fs::read_to_string(restored_dir.path().join("acme/acme.rs")).unwrap(),
+            "// acme"
+        );
+        assert!(!cache
+            .restore("other", tempfile::tempdir().unwrap().path())
+            .unwrap());
+    }
+
+    #[test]
+    fn existing_entries_are_kept() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(cache_dir.path());
+        let generated_dir = tempfile::tempdir().unwrap();
+
+        write_files(generated_dir.path(), &[("mod.rs", "first")]);
+        cache.store("key", generated_dir.path()).unwrap();
+        write_files(generated_dir.path(), &[("mod.rs", "second")]);
+        cache.store("key", generated_dir.path()).unwrap();
+
+        let restored_dir = tempfile::tempdir().unwrap();
+        cache.restore("key", restored_dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(restored_dir.path().join("mod.rs")).unwrap(),
+            "first"
+        );
+    }
+
+    #[test]
+    fn stats_count_entries_but_not_staging_directories() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(cache_dir.path().join("cache"));
+        assert_eq!(cache.stats().unwrap(), CacheStats::default());
+
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(
+            generated_dir.path(),
+            &[("mod.rs", "1234"), ("a/a.rs", "56")],
+        );
+        cache.store("a", generated_dir.path()).unwrap();
+        cache.store("b", generated_dir.path()).unwrap();
+        write_files(cache.dir(), &[(".c-1234/mod.rs", "partial")]);
+
+        assert_eq!(
+            cache.stats().unwrap(),
+            CacheStats {
+                entries: 2,
+                size_bytes: 12,
+            }
+        );
+
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        assert_eq!(cache.stats().unwrap(), CacheStats::default());
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn default_dir_is_in_the_target_directory() {
+        let out_dir = || Some(OsString::from("/work/target/debug/build/acme-0123/out"));
+        let target = || Some(OsString::from("x86_64-unknown-linux-gnu"));
+
+        assert_eq!(
+            default_dir(None, None, None),
+            Path::new("target/grpc-build-cache")
+        );
+        assert_eq!(
+            default_dir(Some(OsString::from("/tmp/target")), out_dir(), target()),
+            Path::new("/tmp/target/grpc-build-cache")
+        );
+        assert_eq!(
+            default_dir(None, out_dir(), target()),
+            Path::new("/work/target/grpc-build-cache")
+        );
+        assert_eq!(
+            default_dir(
+                None,
+                Some(OsString::from(
+                    "/work/target/x86_64-unknown-linux-gnu/release/build/acme-0123/out"
+                )),
+                target()
+            ),
+            Path::new("/work/target/grpc-build-cache")
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use crate::manifest::{hash_file, Manifest};
+use similar::TextDiff;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The result of [`Builder::check`](crate::Builder::check).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// The files of the output directory that do not match the generated code.
+    pub outdated: Vec<OutdatedFile>,
+}
+
+/// A file of the output directory that does not match the generated code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutdatedFile {
+    /// The path of the file, relative to the output directory.
+    pub path: PathBuf,
+    pub kind: Outdated,
+    /// A unified diff from the current contents to the generated ones.
+    pub diff: String,
+}
+
+/// How a file of the output directory is out of date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outdated {
+    /// The file would be generated, but does not exist.
+    Missing,
+    /// The file exists, but its contents differ from the generated ones.
+    Changed,
+    /// The file was generated by a previous run, but would now be removed.
+    Stale,
+}
+
+impl CheckReport {
+    pub fn is_up_to_date(&self) -> bool {
+        self.outdated.is_empty()
+    }
+
+    /// Compares the files generated in `generated_dir` with `out_dir`, which was last generated
+    /// according to `previous`.
+    pub(crate) fn compare(
+        generated_dir: &Path,
+        manifest: &Manifest,
+        out_dir: &Path,
+        previous: Option<&Manifest>,
+    ) -> io::Result<Self> {
+        let mut report = CheckReport::default();
+
+        for (path, hash) in &manifest.outputs {
+            let current = out_dir.join(path);
+            let kind = match hash_file(&current) {
+                Ok(actual) if &actual == hash => continue,
+                Ok(_) => Outdated::Changed,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Outdated::Missing,
+                Err(e) => return Err(e),
+            };
+
+            let old = match kind {
+                Outdated::Changed => read_lossy(&current)?,
+                _ => String::new(),
+            };
+            let new = read_lossy(&generated_dir.join(path))?;
+            report.push(path, kind, &old, &new);
+        }
+
+        for path in previous.iter().flat_map(|previous| previous.outputs.keys()) {
+            let current = out_dir.join(path);
+            if manifest.outputs.contains_key(path) || !current.exists() {
+                continue;
+            }
+
+            let old = read_lossy(&current)?;
+            report.push(path, Outdated::Stale, &old, "");
+        }
+
+        Ok(report)
+    }
+
+    fn push(&mut self, path: &str, kind: Outdated, old: &str, new: &str) {
+        let diff = TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(&format!("a/{}", path), &format!("b/{}", path))
+            .to_string();
+
+        self.outdated.push(OutdatedFile {
+            path: PathBuf::from(path),
+            kind,
+            diff,
+        });
+    }
+}
+
+impl fmt::Display for CheckReport {
+    /// Writes the diffs of every out-of-date file.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.outdated {
+            write!(f, "{}", file.diff)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_lossy(path: &Path) -> io::Result<String> {
+    Ok(String::from_utf8_lossy(&fs::read(path)?).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    /// Generates `generated` and compares it with an output directory last generated with
+    /// `previous`, then changed to `current`.
+    fn compare(
+        generated: &[(&str, &str)],
+        previous: &[(&str, &str)],
+        current: &[(&str, &str)],
+    ) -> CheckReport {
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(generated_dir.path(), generated);
+        let manifest = Manifest::for_dir(generated_dir.path(), String::from("new")).unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        write_files(out_dir.path(), previous);
+        let previous = Manifest::for_dir(out_dir.path(), String::from("old")).unwrap();
+        write_files(out_dir.path(), current);
+
+        CheckReport::compare(
+            generated_dir.path(),
+            &manifest,
+            out_dir.path(),
+            Some(&previous),
+        )
+        .unwrap()
+    }
+
+    fn kinds(report: &CheckReport) -> Vec<(&Path, Outdated)> {
+        report
+            .outdated
+            .iter()
+            .map(|file| (file.path.as_path(), file.kind))
+            .collect()
+    }
+
+    #[test]
+    fn identical_files_are_up_to_date() {
+        let files = [("mod.rs", "pub mod acme;\n"), ("acme.rs", "// acme\n")];
+        assert!(compare(&files, &files, &[]).is_up_to_date());
+    }
+
+    #[test]
+    fn missing_and_changed_files_are_outdated() {
+        let report = compare(
+            &[
+                ("mod.rs", "pub mod acme;\npub mod zeta;\n"),
+                ("zeta.rs", "// zeta\n"),
+            ],
+            &[("mod.rs", "pub mod acme;\n")],
+            &[],
+        );
+
+        assert_eq!(
+            kinds(&report),
+            vec![
+                (Path::new("mod.rs"), Outdated::Changed),
+                (Path::new("zeta.rs"), Outdated::Missing),
+            ]
+        );
+        assert_eq!(
+            report.outdated[0].diff,
+            "--- a/mod.rs\n+++ b/mod.rs\n@@ -1 +1,2 @@\n pub mod acme;\n+pub mod zeta;\n"
+        );
+        assert_eq!(
+            report.outdated[1].diff,
+            "--- a/zeta.rs\n+++ b/zeta.rs\n@@ -0,0 +1 @@\n+// zeta\n"
+        );
+    }
+
+    #[test]
+    fn files_that_would_be_removed_are_stale() {
+        let report = compare(
+            &[("mod.rs", "")],
+            &[
+                ("mod.rs", ""),
+                ("acme.rs", "// acme\n"),
+                ("zeta.rs", "// zeta\n"),
+            ],
+            &[],
+        );
+        assert_eq!(
+            kinds(&report),
+            vec![
+                (Path::new("acme.rs"), Outdated::Stale),
+                (Path::new("zeta.rs"), Outdated::Stale),
+            ]
+        );
+        assert_eq!(
+            report.outdated[0].diff,
+            "--- a/acme.rs\n+++ b/acme.rs\n@@ -1 +0,0 @@\n-// acme\n"
+        );
+    }
+
+    #[test]
+    fn untracked_files_are_ignored() {
+        let report = compare(
+            &[("mod.rs", "")],
+            &[("mod.rs", "")],
+            &[("helpers.rs", "// hand-written\n"), ("README.md", "")],
+        );
+        assert!(report.is_up_to_date());
+    }
+
+    #[test]
+    fn removed_generated_files_are_not_stale() {
+        let out_dir = tempfile::tempdir().unwrap();
+        write_files(out_dir.path(), &[("mod.rs", ""), ("acme.rs", "")]);
+        let previous = Manifest::for_dir(out_dir.path(), String::from("old")).unwrap();
+        fs::remove_file(out_dir.path().join("acme.rs")).unwrap();
+
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(generated_dir.path(), &[("mod.rs", "")]);
+        let manifest = Manifest::for_dir(generated_dir.path(), String::from("new")).unwrap();
+
+        let report = CheckReport::compare(
+            generated_dir.path(),
+            &manifest,
+            out_dir.path(),
+            Some(&previous),
+        )
+        .unwrap();
+        assert!(report.is_up_to_date());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::layout::ModRenderer;
+use crate::module_tree::{module_ident, ModuleTree};
+use crate::tonic_builder::{PROST_VERSION, TONIC_BUILD_VERSION};
+use crate::BuildError;
+use heck::SnakeCase;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The attribute added to every message, enum and oneof when [`Crate::serde`] is enabled.
+pub(crate) const SERDE_ATTRIBUTE: &str =
+    "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]";
+
+/// Options for generating a standalone crate instead of a module tree: the output directory
+/// gets a `Cargo.toml` file, and the generated code is written to `src`, with `src/lib.rs` as
+/// the root module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crate {
+    name: String,
+    version: String,
+    edition: String,
+    serde: bool,
+}
+
+impl Crate {
+    /// A crate named `name`, at version `0.1.0`, using the 2018 edition.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: String::from("0.1.0"),
+            edition: String::from("2018"),
+            serde: false,
+        }
+    }
+
+    /// The version of the crate.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// The edition of the crate.
+    pub fn edition(mut self, edition: impl Into<String>) -> Self {
+        self.edition = edition.into();
+        self
+    }
+
+    /// Add an optional `serde` dependency, and derive `Serialize` and `Deserialize` for every
+    /// message, enum and oneof when the `serde` feature of the crate is enabled.
+    ///
+    /// The well-known types of `prost-types` do not implement them, so messages using them
+    /// do not compile with the feature enabled.
+    pub fn serde(mut self, enable: bool) -> Self {
+        self.serde = enable;
+        self
+    }
+
+    /// Checks that the name of the crate is one cargo accepts.
+    pub(crate) fn check_name(&self) -> Result<(), BuildError> {
+        let ident = self.name.replace('-', "_").to_snake_case();
+        let valid = self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            && self
+                .name
+                .chars()
+                .next()
+                .is_some_and(|c| !c.is_ascii_digit())
+            && module_ident(&ident) == ident;
+
+        if valid {
+            Ok(())
+        } else {
+            Err(BuildError::InvalidCrateName(self.name.clone()))
+        }
+    }
+
+    pub(crate) fn has_serde(&self) -> bool {
+        self.serde
+    }
+
+    /// The contents of the `Cargo.toml` file, depending on `prost-types` if the generated code
+    /// uses the well-known types, and declaring `features`, each with the features it enables.
+    pub(crate) fn manifest(
+        &self,
+        prost_types: bool,
+        features: &BTreeMap<String, Vec<String>>,
+    ) -> String {
+        let mut manifest = format!(
+            "# Generated by grpc-build. Do not edit.\n\
+             [package]\n\
+             name = \"{}\"\n\
+             version = \"{}\"\n\
+             edition = \"{}\"\n\
+             \n\
+             [dependencies]\n\
+             prost = \"{}\"\n",
+            self.name, self.version, self.edition, PROST_VERSION
+        );
+
+        if prost_types {
+            manifest.push_str(&format!("prost-types = \"{}\"\n", PROST_VERSION));
+        }
+        manifest.push_str(&format!("tonic = \"{}\"\n", TONIC_BUILD_VERSION));
+        if self.serde {
+            manifest.push_str(
+                "serde = { version = \"1\", features = [\"derive\"], optional = true }\n",
+            );
+        }
+
+        if !features.is_empty() {
+            manifest.push_str("\n[features]\n");
+            for (feature, enables) in features {
+                let enables: Vec<String> = enables
+                    .iter()
+                    .map(|enabled| format!("\"{}\"", enabled))
+                    .collect();
+                manifest.push_str(&format!("{} = [{}]\n", feature, enables.join(", ")));
+            }
+        }
+
+        manifest
+    }
+}
+
+/// Writes the root module of another renderer to `lib.rs` instead of its root file.
+#[derive(Debug)]
+pub(crate) struct LibRenderer(pub Arc<dyn ModRenderer>);
+
+impl ModRenderer for LibRenderer {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        self.0.render(tree, out_dir)?;
+        fs::rename(out_dir.join(self.0.root_file()), out_dir.join("lib.rs"))
+    }
+
+    fn destination(&self, module: &ModuleTree) -> Option<String> {
+        self.0.destination(module).map(|destination| {
+            if destination == self.0.root_file() {
+                String::from("lib.rs")
+            } else {
+                destination
+            }
+        })
+    }
+
+    fn root_file(&self) -> &str {
+        "lib.rs"
+    }
+}
+
+/// Whether the generated code in `dir` refers to the well-known types of `prost-types`.
+pub(crate) fn uses_prost_types(dir: &Path) -> io::Result<bool> {
+    let mut files = vec![];
+    crate::manifest::walk_files(dir, &mut files)?;
+
+    for file in files {
+        if file.extension() == Some(OsStr::new("rs"))
+            && fs::read_to_string(&file)?.contains("::prost_types::")
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::SingleFileRenderer;
+
+    #[test]
+    fn manifest_depends_on_the_generating_versions() {
+        assert_eq!(
+            Crate::new("acme-protos").manifest(false, &BTreeMap::new()),
+            "\
+# Generated by grpc-build. Do not edit.
+[package]
+name = \"acme-protos\"
+version = \"0.1.0\"
+edition = \"2018\"
+
+[dependencies]
+prost = \"0.9\"
+tonic = \"0.6\"
+"
+        );
+    }
+
+    #[test]
+    fn manifest_has_optional_dependencies() {
+        let manifest = Crate::new("acme-protos")
+            .version("1.2.3")
+            .serde(true)
+            .manifest(true, &BTreeMap::new());
+
+        assert!(manifest.contains("version = \"1.2.3\"\n"));
+        assert!(manifest.contains("prost-types = \"0.9\"\n"));
+        assert!(manifest
+            .contains("serde = { version = \"1\", features = [\"derive\"], optional = true }\n"));
+    }
+
+    #[test]
+    fn manifest_declares_features() {
+        let mut features = BTreeMap::new();
+        crate::ServiceFeatures::new()
+            .client("grpc-client")
+            .declare(&mut features);
+        features.insert(
+            String::from("pkg-acme"),
+            vec![String::from("pkg-zeta"), String::from("pkg-common")],
+        );
+
+        assert!(Crate::new("acme-protos")
+            .manifest(false, &features)
+            .ends_with(
+                "\n[features]\ngrpc-client = []\npkg-acme = [\"pkg-zeta\", \"pkg-common\"]\nserver = []\n"
+            ));
+    }
+
+    #[test]
+    fn lib_renderer_renames_the_root_file_of_its_renderer() {
+        let out_dir = tempfile::tempdir().unwrap();
+        fs::write(out_dir.path().join("zeta.rs"), "// zeta.rs\n").unwrap();
+
+        let renderer = LibRenderer(Arc::new(SingleFileRenderer {
+            file_name: String::from("protogen.rs"),
+        }));
+        let tree = ModuleTree::new(&["zeta.rs"], None);
+        renderer.render(&tree, out_dir.path()).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("lib.rs")).unwrap(),
+            "pub mod zeta {\n// zeta.rs\n}\n"
+        );
+        assert_eq!(renderer.destination(&tree.children[0]).unwrap(), "lib.rs");
+    }
+
+    #[test]
+    fn crate_names_are_checked() {
+        for name in ["acme-protos", "acme_protos", "AcmeProtos", "protos2"] {
+            assert!(Crate::new(name).check_name().is_ok(), "{}", name);
+        }
+        for name in [
+            "",
+            "acme.protos",
+            "acme protos",
+            "2protos",
+            "type",
+            "self",
+            "async",
+        ] {
+            assert!(Crate::new(name).check_name().is_err(), "{}", name);
+        }
+    }
+}
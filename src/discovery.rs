@@ -0,0 +1,313 @@
+use crate::BuildError;
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The protobuf files found in the input directories.
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    /// The files that will be compiled.
+    pub protos: Vec<PathBuf>,
+    /// The files that were found but filtered out.
+    pub skipped: Vec<SkippedProto>,
+}
+
+/// A protobuf file that was filtered out during discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedProto {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Why a protobuf file was filtered out during discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Matched by a `.gitignore` or `.ignore` file.
+    Ignored,
+    /// Matched by the given `!`-prefixed exclude pattern.
+    Excluded(String),
+    /// Include patterns were given, but none of them matched.
+    NotIncluded,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Ignored => write!(f, "ignored by a .gitignore or .ignore file"),
+            SkipReason::Excluded(pattern) => write!(f, "excluded by `{}`", pattern),
+            SkipReason::NotIncluded => write!(f, "not matched by any include pattern"),
+        }
+    }
+}
+
+struct Pattern {
+    original: String,
+    matcher: GlobMatcher,
+}
+
+/// Include/exclude glob patterns, matched against paths relative to their input directory.
+struct Filter {
+    includes: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+}
+
+impl Filter {
+    fn new(globs: &[String]) -> Result<Self, BuildError> {
+        let mut filter = Filter {
+            includes: vec![],
+            excludes: vec![],
+        };
+
+        for original in globs {
+            let (patterns, glob) = match original.strip_prefix('!') {
+                Some(glob) => (&mut filter.excludes, glob),
+                None => (&mut filter.includes, original.as_str()),
+            };
+
+            patterns.push(Pattern {
+                original: original.clone(),
+                matcher: compile_glob(glob)
+                    .map_err(|source| BuildError::InvalidGlob {
+                        pattern: original.clone(),
+                        source,
+                    })?
+                    .compile_matcher(),
+            });
+        }
+
+        Ok(filter)
+    }
+
+    fn skip_reason(&self, relative_path: &Path) -> Option<SkipReason> {
+        if let Some(exclude) = self
+            .excludes
+            .iter()
+            .find(|pattern| pattern.matcher.is_match(relative_path))
+        {
+            return Some(SkipReason::Excluded(exclude.original.clone()));
+        }
+
+        if !self.includes.is_empty()
+            && !self
+                .includes
+                .iter()
+                .any(|pattern| pattern.matcher.is_match(relative_path))
+        {
+            return Some(SkipReason::NotIncluded);
+        }
+
+        None
+    }
+}
+
+fn compile_glob(glob: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(glob).literal_separator(true).build()
+}
+
+/// Recursively finds the `.proto` files inside the input directories, applying the glob
+/// patterns and, optionally, `.gitignore`/`.ignore` files.
+pub fn discover(
+    input_dirs: &[PathBuf],
+    globs: &[String],
+    respect_ignore_files: bool,
+) -> Result<Discovery, BuildError> {
+    let filter = Filter::new(globs)?;
+    let mut discovery = Discovery::default();
+
+    for input_dir in input_dirs {
+        let walk_error = |source| BuildError::Discovery {
+            path: input_dir.clone(),
+            source,
+        };
+
+        let not_ignored = if respect_ignore_files {
+            let protos = walk_protos(input_dir, true).map_err(walk_error)?;
+            Some(protos.into_iter().collect::<HashSet<_>>())
+        } else {
+            None
+        };
+
+        for path in walk_protos(input_dir, false).map_err(walk_error)? {
+            let relative_path = path.strip_prefix(input_dir).unwrap_or(&path);
+
+            let reason = match &not_ignored {
+                Some(not_ignored) if !not_ignored.contains(&path) => Some(SkipReason::Ignored),
+                _ => filter.skip_reason(relative_path),
+            };
+
+            match reason {
+                None => discovery.protos.push(path),
+                Some(reason) => discovery.skipped.push(SkippedProto { path, reason }),
+            }
+        }
+    }
+
+    Ok(discovery)
+}
+
+fn walk_protos(dir: &Path, respect_ignore_files: bool) -> Result<Vec<PathBuf>, ignore::Error> {
+    let mut protos = vec![];
+
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .follow_links(true)
+        .ignore(respect_ignore_files)
+        .git_ignore(respect_ignore_files)
+        .git_exclude(respect_ignore_files)
+        .parents(respect_ignore_files)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        let entry = entry?;
+        let path = entry.path();
+
+        if entry.file_type().is_some_and(|t| t.is_file())
+            && path.extension() == Some(OsStr::new("proto"))
+        {
+            protos.push(path.to_path_buf());
+        }
+    }
+
+    Ok(protos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+    use std::fs;
+
+    const PROTOS: &[&str] = &[
+        "acme/billing/v1/billing.proto",
+        "acme/billing/v2/billing.proto",
+        "acme/common/money.proto",
+        "google/api/http.proto",
+        "zeta.proto",
+    ];
+
+    fn write_empty_protos(dir: &Path, protos: &[&str]) {
+        let files: Vec<(&str, &str)> = protos.iter().map(|proto| (*proto, "")).collect();
+        write_files(dir, &files);
+    }
+
+    fn discover_in_creation_order(protos: &[&str]) -> Vec<PathBuf> {
+        let input_dir = tempfile::tempdir().unwrap();
+        write_empty_protos(input_dir.path(), protos);
+
+        discover(&[input_dir.path().to_path_buf()], &[], false)
+            .unwrap()
+            .protos
+            .iter()
+            .map(|path| path.strip_prefix(input_dir.path()).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn discovery_does_not_depend_on_creation_order() {
+        let expected: Vec<PathBuf> = PROTOS.iter().map(PathBuf::from).collect();
+
+        let mut reversed = PROTOS.to_vec();
+        reversed.reverse();
+        assert_eq!(discover_in_creation_order(&reversed), expected);
+        assert_eq!(discover_in_creation_order(PROTOS), expected);
+    }
+
+    fn skip_reason(globs: &[&str], relative_path: &str) -> Option<SkipReason> {
+        let globs: Vec<String> = globs.iter().map(|glob| glob.to_string()).collect();
+        Filter::new(&globs)
+            .unwrap()
+            .skip_reason(Path::new(relative_path))
+    }
+
+    #[test]
+    fn every_file_is_compiled_without_include_patterns() {
+        assert_eq!(skip_reason(&[], "acme/billing/v1/billing.proto"), None);
+        assert_eq!(
+            skip_reason(&["!**/testdata/**"], "acme/billing/v1/billing.proto"),
+            None
+        );
+    }
+
+    #[test]
+    fn files_not_matched_by_any_include_pattern_are_skipped() {
+        let globs = ["acme/**", "zeta.proto"];
+        assert_eq!(skip_reason(&globs, "acme/common/money.proto"), None);
+        assert_eq!(skip_reason(&globs, "zeta.proto"), None);
+        assert_eq!(
+            skip_reason(&globs, "google/api/http.proto"),
+            Some(SkipReason::NotIncluded)
+        );
+    }
+
+    #[test]
+    fn exclude_patterns_take_precedence_over_include_patterns() {
+        let globs = ["acme/**", "!**/v2/**"];
+        assert_eq!(skip_reason(&globs, "acme/billing/v1/billing.proto"), None);
+        assert_eq!(
+            skip_reason(&globs, "acme/billing/v2/billing.proto"),
+            Some(SkipReason::Excluded(String::from("!**/v2/**")))
+        );
+    }
+
+    #[test]
+    fn wildcards_do_not_match_path_separators() {
+        assert_eq!(skip_reason(&["*.proto"], "zeta.proto"), None);
+        assert_eq!(
+            skip_reason(&["*.proto"], "acme/common/money.proto"),
+            Some(SkipReason::NotIncluded)
+        );
+
+        let globs = ["**/internal/**"];
+        assert_eq!(skip_reason(&globs, "internal/audit.proto"), None);
+        assert_eq!(
+            skip_reason(&globs, "acme/billing/internal/audit.proto"),
+            None
+        );
+        assert_eq!(
+            skip_reason(&globs, "acme/billing/internal_audit.proto"),
+            Some(SkipReason::NotIncluded)
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let error = Filter::new(&[String::from("!acme/[")]).err().unwrap();
+        assert!(matches!(
+            error,
+            BuildError::InvalidGlob { pattern, .. } if pattern == "!acme/["
+        ));
+    }
+
+    #[test]
+    fn ignore_files_are_only_respected_when_enabled() {
+        let input_dir = tempfile::tempdir().unwrap();
+        write_empty_protos(input_dir.path(), PROTOS);
+        fs::write(input_dir.path().join(".gitignore"), "google/\n").unwrap();
+        let input_dirs = [input_dir.path().to_path_buf()];
+
+        let discovery = discover(&input_dirs, &[], false).unwrap();
+        assert_eq!(discovery.protos.len(), PROTOS.len());
+        assert!(discovery.skipped.is_empty());
+
+        let discovery = discover(&input_dirs, &[String::from("!zeta.proto")], true).unwrap();
+        assert_eq!(discovery.protos.len(), PROTOS.len() - 2);
+        assert_eq!(
+            discovery.skipped,
+            vec![
+                SkippedProto {
+                    path: input_dir.path().join("google/api/http.proto"),
+                    reason: SkipReason::Ignored,
+                },
+                SkippedProto {
+                    path: input_dir.path().join("zeta.proto"),
+                    reason: SkipReason::Excluded(String::from("!zeta.proto")),
+                },
+            ]
+        );
+    }
+}
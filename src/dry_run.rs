@@ -0,0 +1,164 @@
+use crate::layout::ModRenderer;
+use crate::manifest::{relative_path, walk_files};
+use crate::module_tree::ModuleTree;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The result of [`Builder::dry_run`](crate::Builder::dry_run).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRun {
+    /// The protobuf files that would be compiled.
+    pub protos: Vec<PathBuf>,
+    /// The files that would be written, apart from the root module.
+    pub files: Vec<PlannedFile>,
+    /// The modules the generated files would be declared in.
+    pub module_tree: ModuleTree,
+    /// Where the root module would be written: `mod.rs`, or `src/lib.rs` for a standalone
+    /// crate.
+    pub mod_file_path: PathBuf,
+    /// The contents of the root module.
+    pub mod_file: String,
+}
+
+/// A file that would be written, and the file generated by protoc it comes from, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    /// The name of the file generated by protoc, e.g. `acme.billing.v1.rs`, or `None` for the
+    /// files written by grpc-build itself, such as the module files of the directory layout,
+    /// `Cargo.toml` or the manifest.
+    pub generated: Option<String>,
+    /// Where the file would be written, inside the output directory.
+    pub destination: PathBuf,
+}
+
+impl DryRun {
+    /// Reads the plan back from `scratch_dir`, a scratch copy of the output directory `out_dir`
+    /// whose code was generated in `generated_dir`, and from `module_tree`, as rendered by
+    /// `renderer`.
+    pub(crate) fn read(
+        protos: Vec<PathBuf>,
+        module_tree: ModuleTree,
+        renderer: &dyn ModRenderer,
+        scratch_dir: &Path,
+        generated_dir: &Path,
+        out_dir: &Path,
+    ) -> io::Result<Self> {
+        let generated_files = module_tree
+            .modules()
+            .into_iter()
+            .filter_map(|module| {
+                Some((
+                    renderer.destination(module)?,
+                    module.file.as_ref()?.name.clone(),
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut paths = vec![];
+        walk_files(scratch_dir, &mut paths)?;
+
+        let mod_file = generated_dir.join(renderer.root_file());
+        let files = paths
+            .iter()
+            .filter(|path| **path != mod_file)
+            .map(|path| PlannedFile {
+                generated: path
+                    .strip_prefix(generated_dir)
+                    .ok()
+                    .and_then(|_| generated_files.get(&relative_path(generated_dir, path)))
+                    .cloned(),
+                destination: out_dir.join(relative_path(scratch_dir, path)),
+            })
+            .collect();
+
+        Ok(DryRun {
+            protos,
+            files,
+            module_tree,
+            mod_file_path: out_dir.join(relative_path(scratch_dir, &mod_file)),
+            mod_file: fs::read_to_string(mod_file)?,
+        })
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Protobuf files to compile:")?;
+        for proto in &self.protos {
+            writeln!(f, "  {}", proto.display())?;
+        }
+
+        writeln!(f, "Files to write:")?;
+        for file in &self.files {
+            match &file.generated {
+                Some(generated) => {
+                    writeln!(f, "  {} -> {}", generated, file.destination.display())?
+                }
+                None => writeln!(f, "  {}", file.destination.display())?,
+            }
+        }
+
+        writeln!(f, "{}:", self.mod_file_path.display())?;
+        write!(f, "{}", self.mod_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crate_gen::LibRenderer;
+    use crate::layout::{Layout, ModFileStyle};
+    use crate::manifest::MANIFEST_FILE;
+    use crate::test_util::write_files;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_file_of_the_scratch_directory_is_planned() {
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let generated_dir = scratch_dir.path().join("src");
+        write_files(
+            &generated_dir,
+            &[("acme.rs", "// acme"), ("acme.billing.rs", "// billing")],
+        );
+
+        let tree = ModuleTree::new(&["acme.rs", "acme.billing.rs"], None);
+        crate::module_tree::lay_out(&tree, &generated_dir).unwrap();
+        let renderer = LibRenderer(Arc::new(Layout::Directory(ModFileStyle::Named)));
+        renderer.render(&tree, &generated_dir).unwrap();
+        write_files(
+            scratch_dir.path(),
+            &[("Cargo.toml", ""), (MANIFEST_FILE, "")],
+        );
+
+        let out_dir = Path::new("protogen");
+        let dry_run = DryRun::read(
+            vec![],
+            tree,
+            &renderer,
+            scratch_dir.path(),
+            &generated_dir,
+            out_dir,
+        )
+        .unwrap();
+
+        assert_eq!(dry_run.mod_file_path, out_dir.join("src/lib.rs"));
+        assert_eq!(
+            dry_run.files,
+            [
+                (None, MANIFEST_FILE),
+                (None, "Cargo.toml"),
+                (Some("acme.billing.rs"), "src/acme/billing.rs"),
+                (Some("acme.rs"), "src/acme.rs"),
+            ]
+            .iter()
+            .map(|(generated, destination)| PlannedFile {
+                generated: generated.map(String::from),
+                destination: out_dir.join(destination),
+            })
+            .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use crate::imports::ProtoSource;
+use crate::module_tree::{module_ident, ModuleTree};
+use crate::proto_file;
+use crate::BuildError;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Cargo features gating the generated gRPC clients and servers.
+///
+/// Both the clients and the servers are generated, and their modules are wrapped in
+/// `#[cfg(feature = "...")]` attributes, so that consumers of the generated code only compile
+/// the side they need. The crate containing the generated code has to declare the features;
+/// a [`Crate`](crate::Crate) declares them in its `Cargo.toml` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceFeatures {
+    client: String,
+    server: String,
+}
+
+impl Default for ServiceFeatures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServiceFeatures {
+    /// Gates the clients behind a `client` feature and the servers behind a `server` feature.
+    pub fn new() -> Self {
+        Self {
+            client: String::from("client"),
+            server: String::from("server"),
+        }
+    }
+
+    /// The feature gating the clients.
+    pub fn client(mut self, feature: impl Into<String>) -> Self {
+        self.client = feature.into();
+        self
+    }
+
+    /// The feature gating the servers.
+    pub fn server(mut self, feature: impl Into<String>) -> Self {
+        self.server = feature.into();
+        self
+    }
+
+    /// Configures `tonic` to generate both sides behind their feature.
+    pub(crate) fn apply(&self, tonic: tonic_build::Builder) -> tonic_build::Builder {
+        tonic
+            .build_client(true)
+            .build_server(true)
+            .client_mod_attribute(".", cfg_feature(&self.client))
+            .server_mod_attribute(".", cfg_feature(&self.server))
+    }
+
+    /// Adds the features to `features`, the `[features]` table of a `Cargo.toml` file.
+    pub(crate) fn declare(&self, features: &mut BTreeMap<String, Vec<String>>) {
+        features.entry(self.client.clone()).or_default();
+        features.entry(self.server.clone()).or_default();
+    }
+}
+
+fn cfg_feature(feature: &str) -> String {
+    format!("#[cfg(feature = \"{}\")]", feature)
+}
+
+/// Cargo features gating the modules of the generated packages, so that consumers of the
+/// generated code only compile the packages they need.
+///
+/// Each package module at the given depth is wrapped in a `#[cfg(feature = "...")]` attribute,
+/// along with its sub-packages, and so is every shallower package with generated code of its
+/// own. The feature is named after the module path, e.g. `pkg-acme-billing` for `acme::billing`
+/// at depth 2, and enables the features of the packages its protobuf files import. Packages
+/// imported by packageless protobuf files hoisted into the root module are not gated.
+///
+/// The build fails if two modules would get the same feature, e.g. `foo_bar` and `foo::bar`,
+/// or if a feature is also one of the [`ServiceFeatures`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageFeatures {
+    depth: usize,
+    prefix: String,
+}
+
+impl Default for PackageFeatures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PackageFeatures {
+    /// Gates the top-level packages, behind features prefixed with `pkg-`.
+    pub fn new() -> Self {
+        Self {
+            depth: 1,
+            prefix: String::from("pkg-"),
+        }
+    }
+
+    /// The depth of the gated package modules, 1 for the top-level packages. A depth of 0 is
+    /// treated as 1.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    /// The prefix of the feature names.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the features of the modules of `tree`, and adds them to `features`, the
+    /// `[features]` table of a `Cargo.toml` file. `sources` are the compiled protobuf files and
+    /// the files they import, whose imports decide which features enable which.
+    ///
+    /// Fails if two modules, or a module and one of the existing `features`, end up with the
+    /// same feature, e.g. `foo_bar` and `foo::bar`.
+    pub(crate) fn apply(
+        &self,
+        tree: &mut ModuleTree,
+        sources: &[ProtoSource],
+        features: &mut BTreeMap<String, Vec<String>>,
+    ) -> Result<(), BuildError> {
+        self.gate(tree, &mut vec![], &mut BTreeMap::new())?;
+
+        let gates: Vec<Option<String>> = sources
+            .iter()
+            .map(|source| gate_of(tree, proto_file::package(&source.source).as_deref()))
+            .collect();
+
+        let mut dependencies: BTreeMap<Option<String>, BTreeSet<String>> = BTreeMap::new();
+        for (source, gate) in sources.iter().zip(&gates) {
+            for import in source.imports() {
+                let imported = sources
+                    .iter()
+                    .position(|source| source.name == import)
+                    .and_then(|index| gates[index].clone());
+                if let Some(imported) = imported.filter(|imported| Some(imported) != gate.as_ref())
+                {
+                    dependencies
+                        .entry(gate.clone())
+                        .or_default()
+                        .insert(imported);
+                }
+            }
+        }
+
+        // The root module is always compiled, and so are the packages it refers to.
+        let mut ungated = BTreeSet::new();
+        let mut pending: Vec<String> = dependencies
+            .get(&None)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        while let Some(feature) = pending.pop() {
+            if ungated.insert(feature.clone()) {
+                pending.extend(
+                    dependencies
+                        .get(&Some(feature))
+                        .into_iter()
+                        .flatten()
+                        .cloned(),
+                );
+            }
+        }
+
+        ungate(tree, &ungated);
+
+        for module in tree.modules() {
+            if let Some(feature) = &module.feature {
+                if features.contains_key(feature) {
+                    return Err(BuildError::FeatureCollision {
+                        feature: feature.clone(),
+                        first: String::from("the gRPC services"),
+                        second: format!("the package module `{}`", module.name),
+                    });
+                }
+                let enables = dependencies
+                    .get(&Some(feature.clone()))
+                    .into_iter()
+                    .flatten()
+                    .filter(|enabled| !ungated.contains(*enabled))
+                    .cloned()
+                    .collect();
+                features.insert(feature.clone(), enables);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the features of the gated modules nested in `module`, whose path is `path`, and
+    /// records the path of the module each feature gates in `gated`.
+    fn gate(
+        &self,
+        module: &mut ModuleTree,
+        path: &mut Vec<String>,
+        gated: &mut BTreeMap<String, String>,
+    ) -> Result<(), BuildError> {
+        for child in &mut module.children {
+            path.push(child.name.clone());
+            if path.len() >= self.depth || child.file.is_some() {
+                let feature = format!(
+                    "{}{}",
+                    self.prefix,
+                    path.iter()
+                        .map(|name| name.trim_start_matches("r#").replace('_', "-"))
+                        .collect::<Vec<_>>()
+                        .join("-")
+                );
+                let module_path = path.join("::");
+                if let Some(other) = gated.insert(feature.clone(), module_path.clone()) {
+                    return Err(BuildError::FeatureCollision {
+                        feature,
+                        first: format!("the package module `{}`", other),
+                        second: format!("the package module `{}`", module_path),
+                    });
+                }
+                child.feature = Some(feature);
+            } else {
+                self.gate(child, path, gated)?;
+            }
+            path.pop();
+        }
+
+        Ok(())
+    }
+}
+
+/// The feature of the gated module holding `package`, or of the module holding the packageless
+/// protobuf files if `package` is `None`.
+fn gate_of(tree: &ModuleTree, package: Option<&str>) -> Option<String> {
+    let package = match package {
+        Some(package) => package,
+        None => {
+            return tree
+                .children
+                .iter()
+                .find(|child| {
+                    child
+                        .file
+                        .as_ref()
+                        .is_some_and(|file| file.is_packageless())
+                })
+                .and_then(|child| child.feature.clone())
+        }
+    };
+
+    let mut module = tree;
+    for segment in package.split('.') {
+        let name = module_ident(segment);
+        module = module.children.iter().find(|child| child.name == name)?;
+        if module.feature.is_some() {
+            return module.feature.clone();
+        }
+    }
+
+    None
+}
+
+/// Clears the features of the modules nested in `module` that are in `ungated`.
+fn ungate(module: &mut ModuleTree, ungated: &BTreeSet<String>) {
+    for child in &mut module.children {
+        if child
+            .feature
+            .as_ref()
+            .is_some_and(|feature| ungated.contains(feature))
+        {
+            child.feature = None;
+        }
+        ungate(child, ungated);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_tree::Packageless;
+    use std::path::PathBuf;
+
+    /// Compiled protobuf files, named after their path relative to `protos`.
+    fn sources(protos: &[(&str, &str)]) -> Vec<ProtoSource> {
+        protos
+            .iter()
+            .map(|(proto, source)| ProtoSource {
+                path: PathBuf::from(proto),
+                name: proto.trim_start_matches("protos/").to_string(),
+                compiled: true,
+                source: source.to_string(),
+            })
+            .collect()
+    }
+
+    fn features(tree: &ModuleTree) -> Vec<(&str, Option<&str>)> {
+        tree.modules()
+            .into_iter()
+            .skip(1)
+            .map(|module| (module.name.as_str(), module.feature.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn packages_are_gated_at_the_given_depth_and_enable_their_imports() {
+        let mut tree = ModuleTree::new(
+            &[
+                "acme.billing.rs",
+                "acme.billing.v1.rs",
+                "acme.type.rs",
+                "zeta.rs",
+            ],
+            None,
+        );
+        let sources = sources(&[
+            (
+                "protos/acme/billing/billing.proto",
+                "package acme.billing;\nimport \"acme/billing/v1/invoice.proto\";\nimport \"zeta.proto\";",
+            ),
+            ("protos/acme/billing/v1/invoice.proto", "package acme.billing.v1;"),
+            (
+                "protos/acme/type/type.proto",
+                "package acme.type;\nimport \"acme/billing/billing.proto\";\nimport \"google/protobuf/empty.proto\";",
+            ),
+            ("protos/zeta.proto", "package zeta;"),
+        ]);
+
+        let mut declared = BTreeMap::new();
+        PackageFeatures::new()
+            .depth(2)
+            .apply(&mut tree, &sources, &mut declared)
+            .unwrap();
+
+        assert_eq!(
+            features(&tree),
+            vec![
+                ("acme", None),
+                ("billing", Some("pkg-acme-billing")),
+                ("v1", None),
+                ("r#type", Some("pkg-acme-type")),
+                ("zeta", Some("pkg-zeta")),
+            ]
+        );
+        assert_eq!(
+            declared,
+            BTreeMap::from([
+                (
+                    String::from("pkg-acme-billing"),
+                    vec![String::from("pkg-zeta")]
+                ),
+                (
+                    String::from("pkg-acme-type"),
+                    vec![String::from("pkg-acme-billing")]
+                ),
+                (String::from("pkg-zeta"), vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn packages_imported_from_the_root_module_are_not_gated() {
+        let file_names = ["_.rs", "acme.rs", "zeta.rs", "zulu.rs"];
+        let sources = sources(&[
+            ("protos/common.proto", "import \"zeta.proto\";"),
+            ("protos/acme.proto", "package acme;"),
+            ("protos/zeta.proto", "package zeta;\nimport \"zulu.proto\";"),
+            ("protos/zulu.proto", "package zulu;"),
+        ]);
+
+        let mut tree = ModuleTree::new(file_names, Some(&Packageless::Hoist));
+        let mut declared = BTreeMap::new();
+        PackageFeatures::new()
+            .apply(&mut tree, &sources, &mut declared)
+            .unwrap();
+
+        assert_eq!(
+            features(&tree),
+            vec![("acme", Some("pkg-acme")), ("zeta", None), ("zulu", None)]
+        );
+        assert_eq!(declared.keys().collect::<Vec<_>>(), vec!["pkg-acme"]);
+
+        let mut tree = ModuleTree::new(
+            file_names,
+            Some(&Packageless::Module(String::from("types"))),
+        );
+        let mut declared = BTreeMap::new();
+        PackageFeatures::new()
+            .prefix("proto-")
+            .apply(&mut tree, &sources, &mut declared)
+            .unwrap();
+
+        assert_eq!(
+            features(&tree),
+            vec![
+                ("acme", Some("proto-acme")),
+                ("types", Some("proto-types")),
+                ("zeta", Some("proto-zeta")),
+                ("zulu", Some("proto-zulu")),
+            ]
+        );
+        assert_eq!(declared["proto-types"], vec![String::from("proto-zeta")]);
+        assert_eq!(declared["proto-zeta"], vec![String::from("proto-zulu")]);
+    }
+
+    #[test]
+    fn packages_enable_the_packages_imported_from_include_directories() {
+        let mut tree = ModuleTree::new(&["acme.rs", "shared.rs", "zeta.rs"], None);
+        let mut sources = sources(&[
+            (
+                "protos/acme/a.proto",
+                "package acme;\nimport \"shared/money.proto\";",
+            ),
+            ("protos/zeta.proto", "package zeta;"),
+        ]);
+        sources.push(ProtoSource {
+            path: PathBuf::from("vendor/shared/money.proto"),
+            name: String::from("shared/money.proto"),
+            compiled: false,
+            source: String::from("package shared;\nimport \"zeta.proto\";"),
+        });
+
+        let mut declared = BTreeMap::new();
+        PackageFeatures::new()
+            .apply(&mut tree, &sources, &mut declared)
+            .unwrap();
+
+        assert_eq!(
+            features(&tree),
+            vec![
+                ("acme", Some("pkg-acme")),
+                ("shared", Some("pkg-shared")),
+                ("zeta", Some("pkg-zeta")),
+            ]
+        );
+        assert_eq!(declared["pkg-acme"], vec![String::from("pkg-shared")]);
+        assert_eq!(declared["pkg-shared"], vec![String::from("pkg-zeta")]);
+    }
+
+    #[test]
+    fn colliding_features_are_rejected() {
+        let sources = sources(&[]);
+
+        let mut tree = ModuleTree::new(&["foo.bar.rs", "foo_bar.rs"], None);
+        let error = PackageFeatures::new()
+            .depth(2)
+            .apply(&mut tree, &sources, &mut BTreeMap::new())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The cargo feature `pkg-foo-bar` would gate both the package module `foo::bar` and the package module `foo_bar`"
+        );
+
+        let mut tree = ModuleTree::new(&["client.rs"], None);
+        let mut declared = BTreeMap::new();
+        ServiceFeatures::new().declare(&mut declared);
+        let error = PackageFeatures::new()
+            .prefix("")
+            .apply(&mut tree, &sources, &mut declared)
+            .unwrap_err();
+        assert!(
+            matches!(&error, BuildError::FeatureCollision { feature, .. } if feature == "client"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use crate::manifest::walk_files;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::{env, io};
+use thiserror::Error;
+
+/// How the generated files are formatted once they have all been written.
+#[derive(Debug, Clone)]
+pub enum Formatter {
+    /// Leave the generated files as they are.
+    Disabled,
+    /// Run an external `rustfmt` binary over every generated file.
+    Rustfmt(Rustfmt),
+    /// Parse every generated file with `syn` and pretty-print it in-process with
+    /// `prettyplease`, without requiring a `rustfmt` binary.
+    #[cfg(feature = "prettyplease")]
+    PrettyPlease,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Formatter::Rustfmt(Rustfmt::default())
+    }
+}
+
+/// Options for running an external `rustfmt` binary.
+#[derive(Debug, Clone)]
+pub struct Rustfmt {
+    path: PathBuf,
+    edition: String,
+    config_path: Option<PathBuf>,
+}
+
+impl Default for Rustfmt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rustfmt {
+    /// Runs the binary named by the `RUSTFMT` environment variable, or `rustfmt` from the
+    /// `PATH`, with the 2018 edition.
+    pub fn new() -> Self {
+        Self {
+            path: env::var_os("RUSTFMT")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("rustfmt")),
+            edition: String::from("2018"),
+            config_path: None,
+        }
+    }
+
+    /// The `rustfmt` binary to run.
+    pub fn path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// The edition passed to `rustfmt --edition`.
+    pub fn edition(mut self, edition: impl Into<String>) -> Self {
+        self.edition = edition.into();
+        self
+    }
+
+    /// A `rustfmt.toml` passed to `rustfmt --config-path`.
+    pub fn config_path(mut self, config_path: impl AsRef<Path>) -> Self {
+        self.config_path = Some(config_path.as_ref().to_path_buf());
+        self
+    }
+
+    fn format(&self, files: &[PathBuf]) -> Result<(), FormatError> {
+        let mut command = Command::new(&self.path);
+        command.arg("--edition").arg(&self.edition);
+        if let Some(config_path) = &self.config_path {
+            command.arg("--config-path").arg(config_path);
+        }
+
+        let output = command
+            .args(files)
+            .output()
+            .map_err(|source| FormatError::Spawn {
+                program: self.path.clone(),
+                source,
+            })?;
+
+        if !output.status.success() {
+            return Err(FormatError::Rustfmt {
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Why formatting the generated files failed.
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("Failed to run {}", program.display())]
+    Spawn {
+        program: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("rustfmt exited with {status}: {stderr}")]
+    Rustfmt { status: ExitStatus, stderr: String },
+
+    #[error("I/O error on {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[cfg(feature = "prettyplease")]
+    #[error("Failed to parse {}", path.display())]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: syn::Error,
+    },
+}
+
+impl FormatError {
+    /// The error with the paths inside `from` replaced by the same paths inside `to`.
+    pub(crate) fn relocate(self, from: &Path, to: &Path) -> Self {
+        let relocate = |path: PathBuf| match path.strip_prefix(from) {
+            Ok(relative) => to.join(relative),
+            Err(_) => path,
+        };
+
+        match self {
+            FormatError::Rustfmt { status, stderr } => FormatError::Rustfmt {
+                status,
+                stderr: stderr.replace(&from.display().to_string(), &to.display().to_string()),
+            },
+            FormatError::Io { path, source } => FormatError::Io {
+                path: relocate(path),
+                source,
+            },
+            #[cfg(feature = "prettyplease")]
+            FormatError::Parse { path, source } => FormatError::Parse {
+                path: relocate(path),
+                source,
+            },
+            error => error,
+        }
+    }
+}
+
+impl Formatter {
+    /// Formats every `.rs` file inside `dir`.
+    pub(crate) fn format_dir(&self, dir: &Path) -> Result<(), FormatError> {
+        if let Formatter::Disabled = self {
+            return Ok(());
+        }
+
+        let mut files = vec![];
+        walk_files(dir, &mut files).map_err(|source| FormatError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        files.retain(|file| file.extension() == Some(OsStr::new("rs")));
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        match self {
+            Formatter::Disabled => Ok(()),
+            Formatter::Rustfmt(rustfmt) => rustfmt.format(&files),
+            #[cfg(feature = "prettyplease")]
+            Formatter::PrettyPlease => files.iter().try_for_each(|file| pretty_print(file)),
+        }
+    }
+}
+
+#[cfg(feature = "prettyplease")]
+fn pretty_print(path: &Path) -> Result<(), FormatError> {
+    let io_error = |source| FormatError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let code = std::fs::read_to_string(path).map_err(io_error)?;
+    let file = syn::parse_file(&code).map_err(|source| FormatError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    std::fs::write(path, prettyplease::unparse(&file)).map_err(io_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    const UNFORMATTED: &str = "pub   struct A{ pub b:u32 }";
+
+    #[test]
+    fn missing_rustfmt_binaries_fail_to_spawn() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.rs", UNFORMATTED)]);
+
+        let formatter = Formatter::Rustfmt(Rustfmt::new().path(dir.path().join("rustfmt")));
+        let error = formatter.format_dir(dir.path()).unwrap_err();
+        assert!(
+            matches!(&error, FormatError::Spawn { program, .. } if *program == dir.path().join("rustfmt")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn failing_rustfmt_runs_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.rs", UNFORMATTED)]);
+
+        let formatter = Formatter::Rustfmt(Rustfmt::new().path("false"));
+        let error = formatter.format_dir(dir.path()).unwrap_err();
+        assert!(
+            matches!(&error, FormatError::Rustfmt { status, .. } if !status.success()),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn disabled_formatting_leaves_files_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.rs", UNFORMATTED)]);
+
+        Formatter::Disabled.format_dir(dir.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            UNFORMATTED
+        );
+    }
+
+    #[test]
+    fn errors_are_relocated_to_the_output_directory() {
+        let error = FormatError::Io {
+            path: PathBuf::from("/tmp/staging/acme/a.rs"),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        }
+        .relocate(Path::new("/tmp/staging"), Path::new("src/protogen"));
+        assert!(
+            matches!(&error, FormatError::Io { path, .. } if path == Path::new("src/protogen/acme/a.rs")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[cfg(feature = "prettyplease")]
+    #[test]
+    fn prettyplease_formats_in_process() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[("acme/a.rs", UNFORMATTED), ("b.rs", "pub struct B {")],
+        );
+
+        let error = Formatter::PrettyPlease.format_dir(dir.path()).unwrap_err();
+        assert!(
+            matches!(&error, FormatError::Parse { path, .. } if *path == dir.path().join("b.rs")),
+            "{:?}",
+            error
+        );
+
+        std::fs::remove_file(dir.path().join("b.rs")).unwrap();
+        Formatter::PrettyPlease.format_dir(dir.path()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("acme/a.rs")).unwrap(),
+            "pub struct A {\n    pub b: u32,\n}\n"
+        );
+    }
+}
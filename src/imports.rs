@@ -0,0 +1,157 @@
+//! Follows `import` statements the way protoc resolves them, to find every protobuf file it
+//! reads.
+
+use crate::proto_file;
+use crate::BuildError;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A protobuf file read by protoc: either a compiled file or one of its transitive imports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoSource {
+    /// Where the file is on disk.
+    pub path: PathBuf,
+    /// The `/`-separated name protoc knows the file by, relative to its include root, which is
+    /// how other files import it, e.g. `acme/billing/billing.proto`.
+    pub name: String,
+    /// Whether the file is compiled, rather than only imported.
+    pub compiled: bool,
+    pub source: String,
+}
+
+impl ProtoSource {
+    /// The names of the files imported by this one.
+    pub fn imports(&self) -> Vec<String> {
+        proto_file::imports(&self.source)
+    }
+}
+
+/// Reads the compiled `protos` and, transitively, the files they import from the `includes`
+/// roots, compiled files first.
+///
+/// An import is resolved against the roots in order, like protoc does. Imports that are not
+/// found, such as the well-known types bundled with protoc, are left out.
+pub fn read_protos(
+    protos: &[PathBuf],
+    includes: &[PathBuf],
+) -> Result<Vec<ProtoSource>, BuildError> {
+    let includes: Vec<PathBuf> = includes.iter().map(|root| normalize(root)).collect();
+
+    let mut sources = vec![];
+    let mut by_name = HashMap::new();
+    for proto in protos {
+        let normalized = normalize(proto);
+        let name = includes
+            .iter()
+            .find_map(|root| normalized.strip_prefix(root).ok())
+            .map_or_else(|| to_name(&normalized), to_name);
+
+        by_name.insert(name.clone(), sources.len());
+        sources.push(ProtoSource {
+            path: proto.clone(),
+            name,
+            compiled: true,
+            source: read(proto)?,
+        });
+    }
+
+    let mut pending: VecDeque<usize> = (0..sources.len()).collect();
+    while let Some(index) = pending.pop_front() {
+        for import in sources[index].imports() {
+            if by_name.contains_key(&import) {
+                continue;
+            }
+
+            let path = includes
+                .iter()
+                .map(|root| root.join(&import))
+                .find(|path| path.is_file());
+            if let Some(path) = path {
+                by_name.insert(import.clone(), sources.len());
+                pending.push_back(sources.len());
+                sources.push(ProtoSource {
+                    source: read(&path)?,
+                    path,
+                    name: import,
+                    compiled: false,
+                });
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+fn read(path: &Path) -> Result<String, BuildError> {
+    fs::read_to_string(path).map_err(|source| BuildError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// `path` without its `.` components, so that `./protos/a.proto` and `protos/a.proto` compare
+/// equal, and `.` becomes empty.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn to_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    #[test]
+    fn imports_are_resolved_against_the_include_roots_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[
+                (
+                    "protos/svc/acme/a.proto",
+                    "import \"shared/money.proto\";\nimport \"common/x.proto\";\nimport \"google/protobuf/empty.proto\";",
+                ),
+                ("protos/svc/acme/b.proto", "import \"acme/a.proto\";"),
+                ("protos/common/x.proto", "import \"shared/money.proto\";"),
+                ("vendor/shared/money.proto", "package shared;"),
+                ("protos/shared/money.proto", "package shadowed;"),
+            ],
+        );
+        let svc = dir.path().join("protos/svc");
+        let protos = [svc.join("acme/a.proto"), svc.join("acme/b.proto")];
+        let includes = [
+            svc.clone(),
+            dir.path().join("vendor"),
+            dir.path().join("protos"),
+        ];
+
+        let sources = read_protos(&protos, &includes).unwrap();
+        let names: Vec<(&str, bool)> = sources
+            .iter()
+            .map(|source| (source.name.as_str(), source.compiled))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("acme/a.proto", true),
+                ("acme/b.proto", true),
+                ("shared/money.proto", false),
+                ("common/x.proto", false),
+            ]
+        );
+        assert_eq!(
+            sources[2].path,
+            dir.path().join("vendor/shared/money.proto")
+        );
+        assert_eq!(sources[2].source, "package shared;");
+    }
+}
@@ -0,0 +1,657 @@
+use crate::manifest::remove_file_and_empty_parents;
+use crate::module_tree::ModuleTree;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes the Rust modules declaring the generated files.
+///
+/// Renderers run once the generated files have been moved to the paths recorded in the
+/// module tree, and before the output directory is formatted. The `Debug` representation of a
+/// renderer is part of the hash deciding whether the code has to be regenerated, so it should
+/// include every option influencing the output.
+pub trait ModRenderer: fmt::Debug + Send + Sync {
+    /// Writes the modules of `tree` to `out_dir`.
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()>;
+
+    /// The `/`-separated path, relative to the output directory, that the generated file of
+    /// `module` is found at once rendered. Defaults to where the module tree laid it out, for
+    /// renderers that do not move the generated files.
+    fn destination(&self, module: &ModuleTree) -> Option<String> {
+        module.file.as_ref().map(|file| file.path.clone())
+    }
+
+    /// The `/`-separated path, relative to the output directory, of the file the root module is
+    /// written to. Defaults to `mod.rs`.
+    fn root_file(&self) -> &str {
+        "mod.rs"
+    }
+}
+
+/// The built-in [`ModRenderer`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// [`PathRenderer`], the default.
+    #[default]
+    Path,
+    /// [`IncludeRenderer`].
+    Include,
+    /// [`DirectoryRenderer`] with the given module file style.
+    Directory(ModFileStyle),
+    /// [`SingleFileRenderer`], writing to `mod.rs`.
+    SingleFile,
+}
+
+impl ModRenderer for Layout {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        match *self {
+            Layout::Path => PathRenderer.render(tree, out_dir),
+            Layout::Include => IncludeRenderer.render(tree, out_dir),
+            Layout::Directory(style) => DirectoryRenderer { style }.render(tree, out_dir),
+            Layout::SingleFile => SingleFileRenderer::default().render(tree, out_dir),
+        }
+    }
+
+    fn destination(&self, module: &ModuleTree) -> Option<String> {
+        match *self {
+            Layout::Path => PathRenderer.destination(module),
+            Layout::Include => IncludeRenderer.destination(module),
+            Layout::Directory(style) => DirectoryRenderer { style }.destination(module),
+            Layout::SingleFile => SingleFileRenderer::default().destination(module),
+        }
+    }
+}
+
+/// Writes a `mod.rs` file with `#[path = "..."] pub mod x;` declarations. The output directory
+/// has to be part of the crate's source tree, e.g. `src/protogen`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PathRenderer;
+
+impl ModRenderer for PathRenderer {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        display(tree, &mut File::create(out_dir.join("mod.rs"))?)
+    }
+}
+
+/// Writes a `mod.rs` file with nested `pub mod x { include!("..."); }` blocks, so that it can
+/// itself be pulled in with `include!`, e.g. when generating into `OUT_DIR` from a build
+/// script:
+///
+/// ```ignore
+/// pub mod protogen {
+///     include!(concat!(env!("OUT_DIR"), "/protogen/mod.rs"));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IncludeRenderer;
+
+impl ModRenderer for IncludeRenderer {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        display_include(tree, &mut File::create(out_dir.join("mod.rs"))?)
+    }
+}
+
+/// Where [`DirectoryRenderer`] puts the module file of a module with sub-modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ModFileStyle {
+    /// `billing/mod.rs`.
+    #[default]
+    ModRs,
+    /// `billing.rs` next to the `billing` directory, as allowed since the 2018 edition.
+    Named,
+}
+
+/// Writes one module file per module, in a directory tree mirroring the packages, so that the
+/// modules are found without `#[path]` attributes.
+///
+/// Each module file holds the generated code of its package, if any, followed by the
+/// declarations of its direct sub-modules. Packages without sub-packages are written to
+/// `name.rs` in the directory of their parent, and the root module to `mod.rs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DirectoryRenderer {
+    pub style: ModFileStyle,
+}
+
+impl DirectoryRenderer {
+    /// The path of the module file of `module`, whose parent module lives in `dir`.
+    fn module_file(&self, module: &ModuleTree, dir: &str) -> String {
+        if module.is_root() {
+            return String::from("mod.rs");
+        }
+
+        let name = module.name.trim_start_matches("r#");
+        let path = match self.style {
+            ModFileStyle::ModRs if !module.children.is_empty() => format!("{}/mod.rs", name),
+            _ => format!("{}.rs", name),
+        };
+
+        if dir.is_empty() {
+            path
+        } else {
+            format!("{}/{}", dir, path)
+        }
+    }
+
+    fn render_module(&self, module: &ModuleTree, dir: &str, out_dir: &Path) -> io::Result<()> {
+        let mut contents = vec![];
+        if let Some(generated) = &module.file {
+            write_packageless_import(module, &mut contents)?;
+            contents.extend(fs::read(out_dir.join(&generated.path))?);
+        }
+        for child in &module.children {
+            contents.extend(cfg_attribute(child).bytes());
+            contents.extend(format!("pub mod {};\n", child.name).bytes());
+        }
+
+        let module_file = self.module_file(module, dir);
+        let path = out_dir.join(&module_file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+
+        if let Some(generated) = &module.file {
+            if generated.path != module_file {
+                fs::remove_file(out_dir.join(&generated.path))?;
+            }
+        }
+
+        let child_dir = match (module.is_root(), dir.is_empty()) {
+            (true, _) => String::new(),
+            (false, true) => module.name.trim_start_matches("r#").to_string(),
+            (false, false) => format!("{}/{}", dir, module.name.trim_start_matches("r#")),
+        };
+        for child in &module.children {
+            self.render_module(child, &child_dir, out_dir)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ModRenderer for DirectoryRenderer {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        self.render_module(tree, "", out_dir)
+    }
+
+    fn destination(&self, module: &ModuleTree) -> Option<String> {
+        // Generated files are laid out in the directory of their parent module.
+        let generated = module.file.as_ref()?;
+        let dir = generated.path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        Some(self.module_file(module, dir))
+    }
+}
+
+/// Writes a single self-contained file, with the generated code of every package inlined in
+/// nested `pub mod x { ... }` blocks. The generated files are removed.
+///
+/// Like [`IncludeRenderer`], the file can be pulled in with `include!`, e.g. from `OUT_DIR`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SingleFileRenderer {
+    /// The name of the written file, `mod.rs` by default.
+    pub file_name: String,
+}
+
+impl Default for SingleFileRenderer {
+    fn default() -> Self {
+        Self {
+            file_name: String::from("mod.rs"),
+        }
+    }
+}
+
+impl ModRenderer for SingleFileRenderer {
+    fn render(&self, tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+        let mut contents = vec![];
+        display_inline(tree, out_dir, &mut contents)?;
+
+        for file in tree.files() {
+            remove_file_and_empty_parents(out_dir, &file.path)?;
+        }
+
+        fs::write(out_dir.join(&self.file_name), contents)
+    }
+
+    fn destination(&self, module: &ModuleTree) -> Option<String> {
+        module.file.as_ref().map(|_| self.file_name.clone())
+    }
+
+    fn root_file(&self) -> &str {
+        &self.file_name
+    }
+}
+
+/// Writes the module tree as nested inline modules holding the contents of the generated
+/// files, read from `out_dir`.
+fn display_inline(tree: &ModuleTree, out_dir: &Path, file: &mut impl Write) -> io::Result<()> {
+    if !tree.is_root() {
+        file.write_all(cfg_attribute(tree).as_bytes())?;
+        file.write_all(format!("pub mod {} {{\n", tree.name).as_bytes())?;
+    }
+
+    if let Some(generated) = &tree.file {
+        write_packageless_import(tree, file)?;
+        file.write_all(&fs::read(out_dir.join(&generated.path))?)?;
+    }
+    for child in &tree.children {
+        display_inline(child, out_dir, file)?;
+    }
+
+    if !tree.is_root() {
+        file.write_all(b"}\n")?;
+    }
+
+    Ok(())
+}
+
+/// Writes the module tree as `#[path]` module declarations for the packages without
+/// sub-packages, and as inline modules for the others.
+///
+/// A package that also has sub-packages pulls in its own generated file with `include!`, as a
+/// module cannot be declared both with `#[path]` and inline.
+fn display(tree: &ModuleTree, file: &mut impl Write) -> io::Result<()> {
+    if tree.is_root() {
+        write_include(tree, file)?;
+        for child in &tree.children {
+            display(child, file)?;
+        }
+
+        return Ok(());
+    }
+
+    file.write_all(cfg_attribute(tree).as_bytes())?;
+    match &tree.file {
+        Some(generated) if tree.children.is_empty() && !generated.is_packageless() => {
+            file.write_all(format!("#[path = \"{}\"]\n", generated.name).as_bytes())?;
+            file.write_all(format!("pub mod {};\n", tree.name).as_bytes())?;
+        }
+        _ => {
+            file.write_all(format!("pub mod {} {{\n", tree.name).as_bytes())?;
+            write_include(tree, file)?;
+            for child in &tree.children {
+                display(child, file)?;
+            }
+            file.write_all(b"}\n")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the module tree as nested inline modules that `include!` each generated file, with
+/// paths relative to the directory of the written file.
+///
+/// Unlike `#[path]` attributes, this works when the written file is itself pulled in with
+/// `include!`, e.g. from `OUT_DIR` in a build script.
+fn display_include(tree: &ModuleTree, file: &mut impl Write) -> io::Result<()> {
+    if tree.is_root() {
+        write_include(tree, file)?;
+        for child in &tree.children {
+            display_include(child, file)?;
+        }
+
+        return Ok(());
+    }
+
+    file.write_all(cfg_attribute(tree).as_bytes())?;
+    file.write_all(format!("pub mod {} {{\n", tree.name).as_bytes())?;
+    write_include(tree, file)?;
+    for child in &tree.children {
+        display_include(child, file)?;
+    }
+    file.write_all(b"}\n")?;
+
+    Ok(())
+}
+
+/// The `#[cfg(feature = "...")]` line gating the declaration of `module`, if it has a feature.
+fn cfg_attribute(module: &ModuleTree) -> String {
+    match &module.feature {
+        Some(feature) => format!("#[cfg(feature = \"{}\")]\n", feature),
+        None => String::new(),
+    }
+}
+
+/// Writes the `include!` of the generated file of `tree`, if it has one.
+fn write_include(tree: &ModuleTree, file: &mut impl Write) -> io::Result<()> {
+    if let Some(generated) = &tree.file {
+        write_packageless_import(tree, file)?;
+        file.write_all(format!("include!(\"{}\");\n", generated.path).as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Writes `use super::*;` if `tree` holds the packageless file under a named module, as prost
+/// refers to other packages relative to the root module from it.
+fn write_packageless_import(tree: &ModuleTree, file: &mut impl Write) -> io::Result<()> {
+    match &tree.file {
+        Some(generated) if generated.is_packageless() && !tree.is_root() => {
+            file.write_all(b"use super::*;\n")
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module_tree::Packageless;
+
+    type Display = fn(&ModuleTree, &mut Vec<u8>) -> io::Result<()>;
+
+    fn render(file_names: &[&str], packageless: Option<&Packageless>, display: Display) -> String {
+        let mut mod_file = vec![];
+        display(&ModuleTree::new(file_names, packageless), &mut mod_file).unwrap();
+
+        String::from_utf8(mod_file).unwrap()
+    }
+
+    /// Packages that are both a leaf and a parent, at several depths.
+    const NESTED_FILES: &[&str] = &[
+        "acme.rs",
+        "acme.billing.rs",
+        "acme.billing.v1.rs",
+        "acme.billing.v1.internal.rs",
+        "acme.billing.v2.rs",
+        "acme.common.rs",
+        "zeta.rs",
+    ];
+
+    #[test]
+    fn keyword_packages_compile_to_escaped_modules() {
+        let mod_file = render(
+            &["acme.type.v1.rs", "foo.match.rs", "google.api.async.rs"],
+            None,
+            display,
+        );
+
+        assert!(mod_file.contains("pub mod r#type {"));
+        assert!(mod_file.contains("#[path = \"foo.match.rs\"]\npub mod r#match;"));
+        assert!(mod_file.contains("#[path = \"google.api.async.rs\"]\npub mod r#async;"));
+    }
+
+    #[test]
+    fn packages_with_sub_packages_are_merged_into_inline_modules() {
+        let expected = "\
+pub mod acme {
+include!(\"acme.rs\");
+pub mod billing {
+include!(\"acme/acme.billing.rs\");
+pub mod v1 {
+include!(\"acme/billing/acme.billing.v1.rs\");
+#[path = \"acme.billing.v1.internal.rs\"]
+pub mod internal;
+}
+#[path = \"acme.billing.v2.rs\"]
+pub mod v2;
+}
+#[path = \"acme.common.rs\"]
+pub mod common;
+}
+#[path = \"zeta.rs\"]
+pub mod zeta;
+";
+
+        assert_eq!(render(NESTED_FILES, None, display), expected);
+    }
+
+    #[test]
+    fn packages_with_sub_packages_are_merged_with_include_layout() {
+        let expected = "\
+pub mod acme {
+include!(\"acme.rs\");
+pub mod billing {
+include!(\"acme/acme.billing.rs\");
+pub mod v1 {
+include!(\"acme/billing/acme.billing.v1.rs\");
+pub mod internal {
+include!(\"acme/billing/v1/acme.billing.v1.internal.rs\");
+}
+}
+pub mod v2 {
+include!(\"acme/billing/acme.billing.v2.rs\");
+}
+}
+pub mod common {
+include!(\"acme/acme.common.rs\");
+}
+}
+pub mod zeta {
+include!(\"zeta.rs\");
+}
+";
+
+        assert_eq!(render(NESTED_FILES, None, display_include), expected);
+    }
+
+    #[test]
+    fn packageless_items_can_be_hoisted() {
+        let file_names = ["_.rs", "acme.common.rs", "zeta.rs"];
+
+        assert_eq!(
+            render(&file_names, Some(&Packageless::Hoist), display),
+            "include!(\"_.rs\");\npub mod acme {\n#[path = \"acme.common.rs\"]\npub mod common;\n}\n#[path = \"zeta.rs\"]\npub mod zeta;\n"
+        );
+        assert_eq!(
+            render(&file_names, Some(&Packageless::Hoist), display_include),
+            "include!(\"_.rs\");\npub mod acme {\npub mod common {\ninclude!(\"acme/acme.common.rs\");\n}\n}\npub mod zeta {\ninclude!(\"zeta.rs\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn packageless_items_can_be_placed_under_a_module() {
+        let file_names = ["_.rs", "acme.common.rs"];
+        let packageless = Packageless::Module(String::from("Types"));
+
+        assert_eq!(
+            render(&file_names, Some(&packageless), display),
+            "pub mod acme {\n#[path = \"acme.common.rs\"]\npub mod common;\n}\npub mod types {\nuse super::*;\ninclude!(\"_.rs\");\n}\n"
+        );
+        assert_eq!(
+            render(&file_names, Some(&packageless), display_include),
+            "pub mod acme {\npub mod common {\ninclude!(\"acme/acme.common.rs\");\n}\n}\npub mod types {\nuse super::*;\ninclude!(\"_.rs\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn layouts_write_the_mod_file_of_their_renderer() {
+        let tree = ModuleTree::new(NESTED_FILES, None);
+
+        for (layout, display) in [
+            (Layout::Path, display as Display),
+            (Layout::Include, display_include),
+        ] {
+            let out_dir = tempfile::tempdir().unwrap();
+            layout.render(&tree, out_dir.path()).unwrap();
+
+            let mut expected = vec![];
+            display(&tree, &mut expected).unwrap();
+            assert_eq!(
+                std::fs::read(out_dir.path().join("mod.rs")).unwrap(),
+                expected
+            );
+        }
+    }
+
+    /// Lays out `file_names`, each holding a comment with its name, renders them and returns the
+    /// resulting files with their contents.
+    fn render_dir(
+        file_names: &[&str],
+        packageless: Option<&Packageless>,
+        renderer: &dyn ModRenderer,
+    ) -> Vec<(String, String)> {
+        let out_dir = tempfile::tempdir().unwrap();
+        for file_name in file_names {
+            std::fs::write(
+                out_dir.path().join(file_name),
+                format!("// {}\n", file_name),
+            )
+            .unwrap();
+        }
+
+        let tree = ModuleTree::new(file_names, packageless);
+        crate::module_tree::lay_out(&tree, out_dir.path()).unwrap();
+        renderer.render(&tree, out_dir.path()).unwrap();
+
+        let mut files = vec![];
+        crate::manifest::walk_files(out_dir.path(), &mut files).unwrap();
+        let files: Vec<(String, String)> = files
+            .iter()
+            .map(|file| {
+                (
+                    crate::manifest::relative_path(out_dir.path(), file),
+                    std::fs::read_to_string(file).unwrap(),
+                )
+            })
+            .collect();
+
+        for module in tree.modules() {
+            if let Some(destination) = renderer.destination(module) {
+                assert!(files.iter().any(|(path, _)| *path == destination));
+            }
+        }
+        assert!(files.iter().any(|(path, _)| path == renderer.root_file()));
+
+        files
+    }
+
+    #[test]
+    fn directory_layout_writes_a_mod_file_per_directory() {
+        let files = render_dir(NESTED_FILES, None, &Layout::Directory(ModFileStyle::ModRs));
+
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "acme/billing/mod.rs",
+                    "// acme.billing.rs\npub mod v1;\npub mod v2;\n"
+                ),
+                (
+                    "acme/billing/v1/internal.rs",
+                    "// acme.billing.v1.internal.rs\n"
+                ),
+                (
+                    "acme/billing/v1/mod.rs",
+                    "// acme.billing.v1.rs\npub mod internal;\n"
+                ),
+                ("acme/billing/v2.rs", "// acme.billing.v2.rs\n"),
+                ("acme/common.rs", "// acme.common.rs\n"),
+                (
+                    "acme/mod.rs",
+                    "// acme.rs\npub mod billing;\npub mod common;\n"
+                ),
+                ("mod.rs", "pub mod acme;\npub mod zeta;\n"),
+                ("zeta.rs", "// zeta.rs\n"),
+            ]
+            .into_iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn directory_layout_can_name_module_files_after_their_directory() {
+        let packageless = Packageless::Module(String::from("types"));
+        let files = render_dir(
+            &[
+                "_.rs",
+                "acme.billing.rs",
+                "acme.billing.v1.rs",
+                "acme.r#type.rs",
+            ],
+            Some(&packageless),
+            &DirectoryRenderer {
+                style: ModFileStyle::Named,
+            },
+        );
+
+        assert_eq!(
+            files,
+            vec![
+                ("acme/billing/v1.rs", "// acme.billing.v1.rs\n"),
+                ("acme/billing.rs", "// acme.billing.rs\npub mod v1;\n"),
+                ("acme/type.rs", "// acme.r#type.rs\n"),
+                ("acme.rs", "pub mod billing;\npub mod r#type;\n"),
+                ("mod.rs", "pub mod acme;\npub mod types;\n"),
+                ("types.rs", "use super::*;\n// _.rs\n"),
+            ]
+            .into_iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn single_file_layout_inlines_every_package() {
+        let files = render_dir(
+            &["_.rs", "acme.billing.rs", "acme.billing.v1.rs", "zeta.rs"],
+            Some(&Packageless::Hoist),
+            &Layout::SingleFile,
+        );
+
+        let expected = "\
+// _.rs
+pub mod acme {
+pub mod billing {
+// acme.billing.rs
+pub mod v1 {
+// acme.billing.v1.rs
+}
+}
+}
+pub mod zeta {
+// zeta.rs
+}
+";
+        assert_eq!(
+            files,
+            vec![(String::from("mod.rs"), String::from(expected))]
+        );
+    }
+
+    #[test]
+    fn single_file_layout_keeps_packageless_modules_relative_to_the_root() {
+        let packageless = Packageless::Module(String::from("types"));
+        let files = render_dir(
+            &["_.rs", "zeta.rs"],
+            Some(&packageless),
+            &SingleFileRenderer {
+                file_name: String::from("protogen.rs"),
+            },
+        );
+
+        assert_eq!(
+            files,
+            vec![(
+                String::from("protogen.rs"),
+                String::from(
+                    "pub mod types {\nuse super::*;\n// _.rs\n}\npub mod zeta {\n// zeta.rs\n}\n"
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn gated_modules_are_declared_behind_their_feature() {
+        let mut tree = ModuleTree::new(&["acme.billing.rs", "acme.billing.v1.rs", "zeta.rs"], None);
+        tree.children[0].children[0].feature = Some(String::from("pkg-acme-billing"));
+        tree.children[1].feature = Some(String::from("pkg-zeta"));
+
+        let mut mod_file = vec![];
+        display(&tree, &mut mod_file).unwrap();
+        assert_eq!(
+            String::from_utf8(mod_file).unwrap(),
+            "pub mod acme {\n#[cfg(feature = \"pkg-acme-billing\")]\npub mod billing {\ninclude!(\"acme/acme.billing.rs\");\n#[path = \"acme.billing.v1.rs\"]\npub mod v1;\n}\n}\n#[cfg(feature = \"pkg-zeta\")]\n#[path = \"zeta.rs\"]\npub mod zeta;\n"
+        );
+
+        let mut mod_file = vec![];
+        display_include(&tree, &mut mod_file).unwrap();
+        assert!(String::from_utf8(mod_file).unwrap().ends_with(
+            "#[cfg(feature = \"pkg-zeta\")]\npub mod zeta {\ninclude!(\"zeta.rs\");\n}\n"
+        ));
+    }
+}
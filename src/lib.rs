@@ -1,28 +1,123 @@
-use crate::graph_layout::{display, generate};
-use crate::tonic_builder::compile;
-use petgraph::graph::NodeIndex;
-use std::fs;
-use std::fs::File;
-use std::path::Path;
-use std::process::Command;
+use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
-use tonic_build::Builder;
 
-mod graph_layout;
+mod builder;
+mod cache;
+mod check;
+mod crate_gen;
+mod discovery;
+mod dry_run;
+mod features;
+mod format;
+mod imports;
+mod layout;
+mod manifest;
+mod module_tree;
+mod proto_file;
+#[cfg(test)]
+mod test_util;
 mod tonic_builder;
 
+pub use builder::Builder;
+pub use cache::{Cache, CacheStats};
+pub use check::{CheckReport, Outdated, OutdatedFile};
+pub use crate_gen::Crate;
+pub use discovery::{Discovery, SkipReason, SkippedProto};
+pub use dry_run::{DryRun, PlannedFile};
+pub use features::{PackageFeatures, ServiceFeatures};
+pub use format::{FormatError, Formatter, Rustfmt};
+pub use layout::{
+    DirectoryRenderer, IncludeRenderer, Layout, ModFileStyle, ModRenderer, PathRenderer,
+    SingleFileRenderer,
+};
+pub use module_tree::{GeneratedFile, ModuleTree, Packageless};
+
 #[derive(Error, Debug)]
 pub enum BuildError {
     #[error("The output directory already exists: {0}")]
     OutputDirectoryExistsError(String),
 
-    #[error("Formatting the generated mod.rs file failed: {0}")]
-    FormattingError(String),
+    #[error(
+        "Refusing to overwrite files that were not generated by grpc-build or were edited since the last run, use `force` to overwrite them: {}",
+        .0.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    ModifiedFilesError(Vec<PathBuf>),
+
+    #[error("The {0} directory was not specified")]
+    MissingDirectoryError(String),
+
+    #[error(
+        "Invalid crate name `{0}`: crate names can only contain ASCII letters, digits, `-` and `_`, cannot start with a digit and cannot be a Rust keyword"
+    )]
+    InvalidCrateName(String),
+
+    #[error("Invalid glob pattern `{pattern}`")]
+    InvalidGlob {
+        pattern: String,
+        #[source]
+        source: globset::Error,
+    },
+
+    #[error("Failed to discover the protobuf files in {}", path.display())]
+    Discovery {
+        path: PathBuf,
+        #[source]
+        source: ignore::Error,
+    },
+
+    #[error("Failed to compile the protobuf files")]
+    Protoc(#[source] io::Error),
+
+    #[error(
+        "Protobuf files without a package declaration need `Builder::packageless` (`--hoist-packageless` or `--packageless-module` on the command line) to either hoist their items into the root module or place them under a named module: {}",
+        .0.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    PackagelessProtos(Vec<PathBuf>),
+
+    #[error(
+        "Cannot place the protobuf files without a package declaration under the module `{module}`, {reason}: {}",
+        protos.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    PackagelessModule {
+        module: String,
+        reason: String,
+        protos: Vec<PathBuf>,
+    },
+
+    #[error("The cargo feature `{feature}` would gate both {first} and {second}")]
+    FeatureCollision {
+        feature: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("Failed to lay out the generated files")]
+    Layout(#[source] io::Error),
+
+    #[error("I/O error on {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to write the module files in {}", path.display())]
+    ModFileWrite {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
 
-    #[error("{0}")]
-    Error(String),
+    #[error("Failed to format the generated files in {}", path.display())]
+    Format {
+        path: PathBuf,
+        #[source]
+        source: FormatError,
+    },
 }
 
+#[deprecated(note = "use `grpc_build::Builder` instead")]
 pub fn build(
     in_dir: &str,
     out_dir: &str,
@@ -30,92 +125,25 @@ pub fn build(
     build_client: bool,
     force: bool,
 ) -> Result<(), BuildError> {
+    #[allow(deprecated)]
     build_with_config(in_dir, out_dir, build_server, build_client, force, |c| c)
 }
 
+#[deprecated(note = "use `grpc_build::Builder` and `Builder::tonic_config` instead")]
 pub fn build_with_config(
     in_dir: &str,
     out_dir: &str,
     build_server: bool,
     build_client: bool,
     force: bool,
-    user_config: impl FnOnce(Builder) -> Builder,
+    user_config: impl FnOnce(tonic_build::Builder) -> tonic_build::Builder,
 ) -> Result<(), BuildError> {
-    if Path::new(out_dir).exists() {
-        if !force {
-            return Err(BuildError::OutputDirectoryExistsError(String::from(
-                out_dir,
-            )));
-        }
-
-        match fs::remove_dir_all(out_dir) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Failed to remove the output directory: {:?}", e);
-                return Err(BuildError::Error(String::from(
-                    "Could not remove the output directory",
-                )));
-            }
-        };
-    }
-
-    match fs::create_dir_all(out_dir) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Failed to create the output directory: {:?}", e);
-            return Err(BuildError::Error(String::from(
-                "Could not create the output directory",
-            )));
-        }
-    };
-
-    match compile(in_dir, out_dir, build_server, build_client, user_config) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Failed to compile the protos: {:?}", e);
-            return Err(BuildError::Error(String::from(
-                "Failed the compile the protos",
-            )));
-        }
-    };
-
-    let graph = match generate(out_dir) {
-        Ok(graph) => graph,
-        Err(e) => {
-            eprintln!("Failed to generate the graph: {:?}", e);
-            return Err(BuildError::Error(String::from(
-                "Failed to generate the graph",
-            )));
-        }
-    };
-
-    let mut proto_lib = match File::create(format!("{}/mod.rs", out_dir)) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Failed to create the mod.rs file: {:?}", e);
-            return Err(BuildError::Error(String::from(
-                "Failed to create the mod.rs file",
-            )));
-        }
-    };
-
-    match display(&graph, &mut proto_lib, NodeIndex::from(0)) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Failed to populate the mod.rs file: {:?}", e);
-            return Err(BuildError::Error(String::from(
-                "Failed to populate the mod.rs file",
-            )));
-        }
-    };
-
-    match Command::new("rustfmt")
-        .arg(format!("{}/mod.rs", out_dir))
-        .spawn()
-    {
-        Ok(_) => println!("Successfully formatted the mod.rs file using Rustfmt"),
-        Err(e) => eprintln!("Failed to populate the mod.rs file: {:?}", e),
-    }
-
-    Ok(())
+    Builder::new()
+        .in_dir(in_dir)
+        .out_dir(out_dir)
+        .build_server(build_server)
+        .build_client(build_client)
+        .force(force)
+        .tonic_config(user_config)
+        .build()
 }
@@ -1,39 +1,385 @@
-use grpc_build::build;
+use grpc_build::{
+    Builder, Cache, Crate, Formatter, Layout, ModFileStyle, PackageFeatures, Packageless, Rustfmt,
+    ServiceFeatures,
+};
+use std::path::Path;
+use std::path::PathBuf;
 
+// Parsed once, so boxing the larger variant is not worth it.
+#[allow(clippy::large_enum_variant)]
 #[derive(structopt::StructOpt)]
 pub enum Command {
+    /// Compile the protobuf files and generate the mod.rs file
     Build {
+        #[structopt(flatten)]
+        args: BuildArgs,
+
+        /// Print the files that would be written and the mod.rs file, without writing them
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Generate a standalone crate, with a Cargo.toml file and the code in src
+    Crate {
+        #[structopt(flatten)]
+        args: BuildArgs,
+
+        /// The name of the crate, defaults to the name of the output directory with the characters
+        /// crate names cannot contain replaced by `-`
+        #[structopt(long)]
+        name: Option<String>,
+
+        /// The version of the crate
+        #[structopt(long, default_value = "0.1.0")]
+        crate_version: String,
+
+        /// Add an optional serde feature deriving Serialize and Deserialize for every message
         #[structopt(long)]
-        in_dir: String,
+        serde: bool,
 
+        /// Print the files that would be written and src/lib.rs, without writing them
         #[structopt(long)]
-        out_dir: String,
+        dry_run: bool,
+    },
+    /// Check that the output directory matches the generated code, without modifying it
+    Check(BuildArgs),
+    /// Manage the shared codegen cache
+    Cache {
+        #[structopt(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(structopt::StructOpt)]
+pub struct BuildArgs {
+    /// Directory whose protobuf files are compiled; can be repeated
+    #[structopt(long, required = true, number_of_values = 1)]
+    in_dir: Vec<String>,
+
+    /// Import-only directory passed to protoc but not compiled; can be repeated
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<String>,
+
+    /// Glob filtering the compiled protobuf files, `!`-prefixed to exclude; can be repeated
+    #[structopt(long, number_of_values = 1)]
+    glob: Vec<String>,
+
+    /// Skip protobuf files matched by .gitignore and .ignore files
+    #[structopt(long)]
+    respect_ignore_files: bool,
+
+    /// Print the protobuf files that were skipped and why
+    #[structopt(short, long)]
+    verbose: bool,
+
+    #[structopt(long)]
+    out_dir: String,
+
+    #[structopt(short = "client", long = "build_client")]
+    build_client: bool,
+
+    #[structopt(short = "server", long = "build_server")]
+    build_server: bool,
 
-        #[structopt(short = "client", long = "build_client")]
-        build_client: bool,
+    #[structopt(short = "force", long = "force")]
+    force: bool,
 
-        #[structopt(short = "server", long = "build_server")]
-        build_server: bool,
+    /// Regenerate the code even if the inputs did not change since the last run
+    #[structopt(long)]
+    always_regenerate: bool,
 
-        #[structopt(short = "force", long = "force")]
-        force: bool,
+    /// Generate both the clients and the servers, behind the `client` and `server` cargo features
+    #[structopt(long)]
+    service_features: bool,
+
+    /// The cargo feature gating the clients, implies --service-features
+    #[structopt(long)]
+    client_feature: Option<String>,
+
+    /// The cargo feature gating the servers, implies --service-features
+    #[structopt(long)]
+    server_feature: Option<String>,
+
+    /// Compile the top-level packages behind `pkg-` cargo features, e.g. `pkg-acme`
+    #[structopt(long)]
+    package_features: bool,
+
+    /// The depth of the packages compiled behind cargo features, implies --package-features
+    #[structopt(long)]
+    package_feature_depth: Option<usize>,
+
+    /// The prefix of the package features, implies --package-features
+    #[structopt(long)]
+    package_feature_prefix: Option<String>,
+
+    /// How the generated files are formatted: rustfmt, prettyplease or none
+    #[structopt(long, default_value = "rustfmt")]
+    formatter: String,
+
+    /// How mod.rs pulls in the generated files: path, include, directory (a mod.rs file in each
+    /// directory), directory-named (a billing.rs file next to each billing directory) or
+    /// single-file (all the generated code inlined in mod.rs)
+    #[structopt(long, default_value = "path")]
+    layout: String,
+
+    /// Pull the items of protobuf files without a package declaration into the root module
+    #[structopt(long, conflicts_with = "packageless-module")]
+    hoist_packageless: bool,
+
+    /// Place the items of protobuf files without a package declaration under this module
+    #[structopt(long)]
+    packageless_module: Option<String>,
+
+    /// The rustfmt binary used to format the generated files
+    #[structopt(long)]
+    rustfmt_path: Option<PathBuf>,
+
+    /// The edition passed to rustfmt
+    #[structopt(long, default_value = "2018")]
+    rustfmt_edition: String,
+
+    /// A rustfmt.toml passed to rustfmt
+    #[structopt(long)]
+    rustfmt_config_path: Option<PathBuf>,
+
+    /// Serve identical compile requests from the shared codegen cache
+    #[structopt(long)]
+    cache: bool,
+
+    /// The shared codegen cache directory, implies --cache
+    #[structopt(long)]
+    cache_dir: Option<PathBuf>,
+}
+
+#[derive(structopt::StructOpt)]
+pub enum CacheCommand {
+    /// Print the number of cached entries and their total size
+    Stats {
+        /// Defaults to grpc-build-cache in the cargo target directory
+        #[structopt(long)]
+        cache_dir: Option<PathBuf>,
     },
+    /// Remove every cached entry
+    Clear {
+        /// Defaults to grpc-build-cache in the cargo target directory
+        #[structopt(long)]
+        cache_dir: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        for cause in e.chain().skip(1) {
+            eprintln!("  Caused by: {}", cause);
+        }
+        std::process::exit(1);
+    }
 }
 
-fn main() -> Result<(), anyhow::Error> {
+fn run() -> Result<(), anyhow::Error> {
     let command = <Command as paw::ParseArgs>::parse_args()?;
 
     match command {
-        Command::Build {
+        Command::Build { args, dry_run } => {
+            let verbose = args.verbose;
+            run_build(args.builder()?, verbose, dry_run)?;
+        }
+        Command::Crate {
+            args,
+            name,
+            crate_version,
+            serde,
+            dry_run,
+        } => {
+            let verbose = args.verbose;
+            let name = match name {
+                Some(name) => name,
+                // e.g. `acme-protos` for an `acme.protos` directory.
+                None => Path::new(&args.out_dir)
+                    .file_name()
+                    .map(|name| {
+                        name.to_string_lossy()
+                            .chars()
+                            .map(|c| match c {
+                                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c,
+                                _ => '-',
+                            })
+                            .collect::<String>()
+                    })
+                    .ok_or_else(|| anyhow::anyhow!("Cannot name a crate after {}", args.out_dir))?,
+            };
+
+            let standalone_crate = Crate::new(name).version(crate_version).serde(serde);
+            run_build(
+                args.builder()?.standalone_crate(standalone_crate),
+                verbose,
+                dry_run,
+            )?;
+        }
+        Command::Check(args) => {
+            let report = args.builder()?.check()?;
+            if !report.is_up_to_date() {
+                print!("{}", report);
+                anyhow::bail!(
+                    "{} generated file(s) are out of date, run `grpc_build build` to regenerate them",
+                    report.outdated.len()
+                );
+            }
+        }
+        Command::Cache { command } => match command {
+            CacheCommand::Stats { cache_dir } => {
+                let cache = cache_dir.map(Cache::new).unwrap_or_default();
+                let stats = cache.stats()?;
+                println!("Cache directory: {}", cache.dir().display());
+                println!("Entries: {}", stats.entries);
+                println!("Size: {} bytes", stats.size_bytes);
+            }
+            CacheCommand::Clear { cache_dir } => {
+                let cache = cache_dir.map(Cache::new).unwrap_or_default();
+                cache.clear()?;
+                println!("Cleared {}", cache.dir().display());
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn run_build(builder: Builder, verbose: bool, dry_run: bool) -> Result<(), anyhow::Error> {
+    if verbose {
+        for skipped in builder.discover()?.skipped {
+            eprintln!("Skipped {}: {}", skipped.path.display(), skipped.reason);
+        }
+    }
+
+    if dry_run {
+        print!("{}", builder.dry_run()?);
+    } else {
+        builder.build()?;
+    }
+
+    Ok(())
+}
+
+impl BuildArgs {
+    fn builder(self) -> Result<Builder, anyhow::Error> {
+        let BuildArgs {
             in_dir,
+            include,
+            glob,
+            respect_ignore_files,
             out_dir,
             build_client,
             build_server,
             force,
-        } => {
-            build(&in_dir, &out_dir, build_server, build_client, force)?;
-        }
-    }
+            always_regenerate,
+            service_features,
+            client_feature,
+            server_feature,
+            package_features,
+            package_feature_depth,
+            package_feature_prefix,
+            layout,
+            hoist_packageless,
+            packageless_module,
+            formatter,
+            rustfmt_path,
+            rustfmt_edition,
+            rustfmt_config_path,
+            cache,
+            cache_dir,
+            ..
+        } = self;
 
-    Ok(())
+        let layout = match layout.as_str() {
+            "path" => Layout::Path,
+            "include" => Layout::Include,
+            "directory" => Layout::Directory(ModFileStyle::ModRs),
+            "directory-named" => Layout::Directory(ModFileStyle::Named),
+            "single-file" => Layout::SingleFile,
+            other => anyhow::bail!("Unknown layout `{}`", other),
+        };
+
+        let formatter = match formatter.as_str() {
+            "none" => Formatter::Disabled,
+            "rustfmt" => {
+                let mut rustfmt = Rustfmt::new().edition(rustfmt_edition);
+                if let Some(path) = rustfmt_path {
+                    rustfmt = rustfmt.path(path);
+                }
+                if let Some(config_path) = rustfmt_config_path {
+                    rustfmt = rustfmt.config_path(config_path);
+                }
+                Formatter::Rustfmt(rustfmt)
+            }
+            #[cfg(feature = "prettyplease")]
+            "prettyplease" => Formatter::PrettyPlease,
+            #[cfg(not(feature = "prettyplease"))]
+            "prettyplease" => {
+                anyhow::bail!("The prettyplease formatter requires the `prettyplease` feature")
+            }
+            other => anyhow::bail!("Unknown formatter `{}`", other),
+        };
+
+        let builder = in_dir
+            .iter()
+            .fold(Builder::new(), |builder, dir| builder.in_dir(dir));
+        let builder = include
+            .iter()
+            .fold(builder, |builder, dir| builder.include_dir(dir));
+
+        let builder = glob
+            .into_iter()
+            .fold(builder, |builder, pattern| builder.glob(pattern))
+            .respect_ignore_files(respect_ignore_files)
+            .out_dir(out_dir)
+            .build_client(build_client)
+            .build_server(build_server)
+            .force(force)
+            .always_regenerate(always_regenerate)
+            .layout(layout)
+            .formatter(formatter);
+
+        let builder = match (hoist_packageless, packageless_module) {
+            (true, _) => builder.packageless(Packageless::Hoist),
+            (false, Some(module)) => builder.packageless(Packageless::Module(module)),
+            (false, None) => builder,
+        };
+
+        let builder = if service_features || client_feature.is_some() || server_feature.is_some() {
+            let mut service_features = ServiceFeatures::new();
+            if let Some(feature) = client_feature {
+                service_features = service_features.client(feature);
+            }
+            if let Some(feature) = server_feature {
+                service_features = service_features.server(feature);
+            }
+            builder.service_features(service_features)
+        } else {
+            builder
+        };
+
+        let builder = if package_features
+            || package_feature_depth.is_some()
+            || package_feature_prefix.is_some()
+        {
+            let mut package_features = PackageFeatures::new();
+            if let Some(depth) = package_feature_depth {
+                package_features = package_features.depth(depth);
+            }
+            if let Some(prefix) = package_feature_prefix {
+                package_features = package_features.prefix(prefix);
+            }
+            builder.package_features(package_features)
+        } else {
+            builder
+        };
+
+        let builder = match cache_dir {
+            Some(cache_dir) => builder.cache_dir(cache_dir),
+            None => builder.cache(cache),
+        };
+
+        Ok(builder)
+    }
 }
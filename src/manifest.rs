@@ -0,0 +1,341 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file, written at the root of the output directory, recording what was generated.
+pub const MANIFEST_FILE: &str = ".grpc-build-manifest";
+
+const HEADER: &str = "# Generated by grpc-build. Do not edit.";
+
+/// Hashes everything that influences the generated code.
+pub struct InputHasher(Sha256);
+
+impl InputHasher {
+    pub fn new() -> Self {
+        let mut hasher = InputHasher(Sha256::new());
+        hasher.field("grpc-build", env!("CARGO_PKG_VERSION"));
+        for (package, version) in crate::tonic_builder::resolved_versions() {
+            hasher.field(package, version);
+        }
+        hasher
+    }
+
+    /// Adds a named value. Both are length-prefixed, so that adjacent fields cannot collide.
+    pub fn field(&mut self, name: &str, value: impl AsRef<[u8]>) {
+        for bytes in [name.as_bytes(), value.as_ref()] {
+            self.0.update((bytes.len() as u64).to_le_bytes());
+            self.0.update(bytes);
+        }
+    }
+
+    /// Adds the contents of a file and its path relative to `root`, so that the hash does not
+    /// depend on where the protobuf tree is checked out.
+    pub fn file(&mut self, root: &Path, path: &Path) -> io::Result<()> {
+        let contents = fs::read(path)?;
+        self.field("path", relative_path(root, path));
+        self.field("contents", contents);
+        Ok(())
+    }
+
+    pub fn finish(self) -> String {
+        to_hex(&self.0.finalize())
+    }
+}
+
+/// The hashes of the inputs and of every generated file in an output directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub input_hash: String,
+    /// Hashes of the generated files, keyed by their `/`-separated path relative to the
+    /// output directory.
+    pub outputs: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    /// A manifest without any generated file.
+    pub fn empty(input_hash: String) -> Self {
+        Manifest {
+            input_hash,
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Hashes every file in `dir`, except the manifest itself.
+    pub fn for_dir(dir: &Path, input_hash: String) -> io::Result<Self> {
+        let mut files = vec![];
+        walk_files(dir, &mut files)?;
+
+        let mut outputs = BTreeMap::new();
+        for file in files {
+            let relative_path = relative_path(dir, &file);
+            if relative_path != MANIFEST_FILE {
+                outputs.insert(relative_path, hash_file(&file)?);
+            }
+        }
+
+        Ok(Manifest {
+            input_hash,
+            outputs,
+        })
+    }
+
+    /// Reads the manifest of `dir`, if it has a valid one.
+    pub fn read(dir: &Path) -> io::Result<Option<Self>> {
+        let contents = match fs::read_to_string(dir.join(MANIFEST_FILE)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return None;
+        }
+
+        let input_hash = lines.next()?.strip_prefix("input ")?.to_string();
+
+        let mut outputs = BTreeMap::new();
+        for line in lines {
+            let (hash, path) = line.strip_prefix("output ")?.split_once(' ')?;
+            outputs.insert(path.to_string(), hash.to_string());
+        }
+
+        Some(Manifest {
+            input_hash,
+            outputs,
+        })
+    }
+
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let mut contents = format!("{}\ninput {}\n", HEADER, self.input_hash);
+        for (path, hash) in &self.outputs {
+            contents.push_str(&format!("output {} {}\n", hash, path));
+        }
+
+        fs::write(dir.join(MANIFEST_FILE), contents)
+    }
+
+    /// Whether every generated file in `dir` still has the recorded contents.
+    pub fn is_intact(&self, dir: &Path) -> io::Result<bool> {
+        for (path, hash) in &self.outputs {
+            match hash_file(&dir.join(path)) {
+                Ok(actual) if &actual == hash => {}
+                Ok(_) => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The files of `out_dir` that syncing this manifest would overwrite although they are not
+    /// owned by grpc-build: generated files edited since `previous` was written, and files not
+    /// tracked by `previous` at all.
+    pub fn conflicts(&self, out_dir: &Path, previous: &Manifest) -> io::Result<Vec<String>> {
+        let mut conflicts = vec![];
+
+        for (path, hash) in &self.outputs {
+            let actual = match hash_file(&out_dir.join(path)) {
+                Ok(actual) => actual,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            if &actual != hash && previous.outputs.get(path) != Some(&actual) {
+                conflicts.push(path.clone());
+            }
+        }
+
+        for (path, hash) in &previous.outputs {
+            if self.outputs.contains_key(path) {
+                continue;
+            }
+
+            match hash_file(&out_dir.join(path)) {
+                Ok(actual) if &actual != hash => conflicts.push(path.clone()),
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Moves the files of `generated_dir`, described by this manifest, into `out_dir`, which was
+    /// previously generated according to `previous`.
+    ///
+    /// Files whose contents did not change are left alone, so their modification time is
+    /// preserved, and files that are no longer generated are removed. The manifest is moved
+    /// last, so an interrupted sync is redone by the next run.
+    pub fn sync(
+        &self,
+        generated_dir: &Path,
+        out_dir: &Path,
+        previous: &Manifest,
+    ) -> io::Result<()> {
+        for (path, hash) in &self.outputs {
+            let target = out_dir.join(path);
+            match hash_file(&target) {
+                Ok(actual) if &actual == hash => continue,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(generated_dir.join(path), target)?;
+        }
+
+        for path in previous.outputs.keys() {
+            if !self.outputs.contains_key(path) {
+                remove_file_and_empty_parents(out_dir, path)?;
+            }
+        }
+
+        fs::rename(
+            generated_dir.join(MANIFEST_FILE),
+            out_dir.join(MANIFEST_FILE),
+        )
+    }
+}
+
+/// Removes `dir/relative_path`, and the directories between it and `dir` that are left empty.
+pub fn remove_file_and_empty_parents(dir: &Path, relative_path: &str) -> io::Result<()> {
+    let path = dir.join(relative_path);
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    for parent in path.ancestors().skip(1) {
+        if parent == dir || fs::remove_dir(parent).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    Ok(to_hex(&Sha256::digest(fs::read(path)?)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The `/`-separated path of `file` relative to `dir`.
+pub fn relative_path(dir: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(dir).unwrap_or(file);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Recursively lists the files inside `dir`, in sorted order.
+pub fn walk_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            walk_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    #[test]
+    fn sync_only_touches_changed_and_stale_files() {
+        let out_dir = tempfile::tempdir().unwrap();
+        write_files(
+            out_dir.path(),
+            &[("mod.rs", "old"), ("a/a.rs", "same"), ("b/b.rs", "stale")],
+        );
+        let previous = Manifest::for_dir(out_dir.path(), String::from("old")).unwrap();
+        previous.write(out_dir.path()).unwrap();
+        assert_eq!(
+            Manifest::read(out_dir.path()).unwrap(),
+            Some(previous.clone())
+        );
+
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(
+            generated_dir.path(),
+            &[("mod.rs", "new"), ("a/a.rs", "same")],
+        );
+        let manifest = Manifest::for_dir(generated_dir.path(), String::from("new")).unwrap();
+        manifest.write(generated_dir.path()).unwrap();
+
+        manifest
+            .sync(generated_dir.path(), out_dir.path(), &previous)
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(out_dir.path().join("mod.rs")).unwrap(),
+            "new"
+        );
+        // Unchanged files are not moved out of the generated directory.
+        assert!(generated_dir.path().join("a/a.rs").exists());
+        assert!(!out_dir.path().join("b").exists());
+        assert_eq!(Manifest::read(out_dir.path()).unwrap(), Some(manifest));
+    }
+
+    #[test]
+    fn conflicts_are_edited_or_untracked_files() {
+        let out_dir = tempfile::tempdir().unwrap();
+        write_files(
+            out_dir.path(),
+            &[("mod.rs", "generated"), ("stale.rs", "generated")],
+        );
+        let previous = Manifest::for_dir(out_dir.path(), String::new()).unwrap();
+
+        let generated_dir = tempfile::tempdir().unwrap();
+        write_files(
+            generated_dir.path(),
+            &[("mod.rs", "regenerated"), ("helpers.rs", "generated")],
+        );
+        let manifest = Manifest::for_dir(generated_dir.path(), String::new()).unwrap();
+
+        assert!(manifest
+            .conflicts(out_dir.path(), &previous)
+            .unwrap()
+            .is_empty());
+
+        write_files(
+            out_dir.path(),
+            &[
+                ("mod.rs", "edited"),
+                ("stale.rs", "edited"),
+                ("helpers.rs", "hand-written"),
+                ("README", "hand-written"),
+            ],
+        );
+        assert_eq!(
+            manifest.conflicts(out_dir.path(), &previous).unwrap(),
+            vec!["helpers.rs", "mod.rs", "stale.rs"]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use heck::SnakeCase;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Where the items of the protobuf files without a `package` declaration go.
+///
+/// prost generates them in a file named `_.rs`, which cannot be declared as a module as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packageless {
+    /// Pulled into the root of the `mod.rs` file with `include!`.
+    Hoist,
+    /// Placed under a module with the given name, which must not also be the name of a
+    /// top-level package.
+    Module(String),
+}
+
+/// The file prost generates for the protobuf files without a `package` declaration.
+pub(crate) const PACKAGELESS_FILE: &str = "_.rs";
+
+/// The modules of the generated code, mirroring the protobuf packages.
+///
+/// The tree is built from the names of the files prost generated, e.g. `acme.billing.v1.rs`,
+/// and only describes where each of them goes: laying the files out and writing the `mod.rs`
+/// file is done separately.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleTree {
+    /// The name of the module, as a Rust identifier. Empty for the root module.
+    pub name: String,
+    /// The protobuf package of the module as prost spells it in file names, with escaped
+    /// segments, e.g. `acme.billing` or `acme.r#type`. Empty for the root module and for a
+    /// module holding the protobuf files without a package.
+    pub package: String,
+    /// The generated file with the items of the package, if it has any. A package can both
+    /// have its own file and be the parent of other packages, e.g. `acme.billing` and
+    /// `acme.billing.v1`.
+    pub file: Option<GeneratedFile>,
+    /// The nested modules, sorted by name.
+    pub children: Vec<ModuleTree>,
+    /// The cargo feature the module is compiled behind, if any, e.g. `pkg-acme-billing`. See
+    /// [`PackageFeatures`](crate::PackageFeatures).
+    pub feature: Option<String>,
+}
+
+/// A file generated by prost, and where it is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedFile {
+    /// The name of the file, e.g. `acme.billing.v1.rs`.
+    pub name: String,
+    /// The `/`-separated path of the file relative to the output directory, e.g.
+    /// `acme/billing/acme.billing.v1.rs`. Files are placed in the directory of their parent
+    /// module, next to the files of their sibling packages.
+    pub path: String,
+}
+
+impl GeneratedFile {
+    /// Whether the file holds the items of the protobuf files without a package.
+    ///
+    /// prost refers to other packages relative to the root module from this file.
+    pub fn is_packageless(&self) -> bool {
+        self.name == PACKAGELESS_FILE
+    }
+}
+
+impl ModuleTree {
+    /// Builds the tree of the given generated files. A [`PACKAGELESS_FILE`] is placed
+    /// according to `packageless`, and hoisted if it is `None`.
+    pub fn new<I>(file_names: I, packageless: Option<&Packageless>) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut root = ModuleTree::default();
+
+        for file_name in file_names {
+            let file_name = file_name.as_ref();
+
+            if file_name == PACKAGELESS_FILE {
+                let file = GeneratedFile {
+                    name: file_name.to_string(),
+                    path: file_name.to_string(),
+                };
+                match packageless {
+                    Some(Packageless::Module(module)) => {
+                        root.child(module, String::new()).file = Some(file)
+                    }
+                    _ => root.file = Some(file),
+                }
+                continue;
+            }
+
+            let segments: Vec<&str> = file_name.trim_end_matches(".rs").split('.').collect();
+            let mut module = &mut root;
+            let mut dir = String::new();
+            for (i, segment) in segments.iter().enumerate() {
+                if !module.is_root() {
+                    // Inline modules look for `#[path]` files in a directory named after them.
+                    dir.push_str(module.name.trim_start_matches("r#"));
+                    dir.push('/');
+                }
+
+                module = module.child(segment, segments[..=i].join("."));
+            }
+
+            module.file = Some(GeneratedFile {
+                name: file_name.to_string(),
+                path: format!("{}{}", dir, file_name),
+            });
+        }
+
+        root
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    /// This module and the modules nested in it, depth first.
+    pub fn modules(&self) -> Vec<&ModuleTree> {
+        let mut modules = vec![self];
+        for child in &self.children {
+            modules.extend(child.modules());
+        }
+        modules
+    }
+
+    /// The generated files of this module and of the modules nested in it, depth first.
+    pub fn files(&self) -> Vec<&GeneratedFile> {
+        self.modules()
+            .into_iter()
+            .filter_map(|module| module.file.as_ref())
+            .collect()
+    }
+
+    /// The child module for the package `segment`, inserted in order if it does not exist.
+    fn child(&mut self, segment: &str, package: String) -> &mut ModuleTree {
+        let name = module_ident(segment);
+        let index = match self
+            .children
+            .binary_search_by(|child| child.name.as_str().cmp(&name))
+        {
+            Ok(index) => index,
+            Err(index) => {
+                self.children.insert(
+                    index,
+                    ModuleTree {
+                        name,
+                        package,
+                        file: None,
+                        children: vec![],
+                        feature: None,
+                    },
+                );
+                index
+            }
+        };
+
+        &mut self.children[index]
+    }
+}
+
+/// The names of the files prost generated in `out_dir`, sorted.
+pub(crate) fn generated_files(out_dir: &Path) -> io::Result<Vec<String>> {
+    let mut file_names = vec![];
+    for entry in fs::read_dir(out_dir)? {
+        file_names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    file_names.sort();
+
+    Ok(file_names)
+}
+
+/// Moves the generated files of `tree`, which prost wrote to the root of `out_dir`, to their
+/// path in the tree.
+pub(crate) fn lay_out(tree: &ModuleTree, out_dir: &Path) -> io::Result<()> {
+    for file in tree.files() {
+        if file.path == file.name {
+            continue;
+        }
+
+        let path = out_dir.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(out_dir.join(&file.name), path)?;
+    }
+
+    Ok(())
+}
+
+/// Whether a top-level package would end up in the same module as the packageless protobuf
+/// files, if they were placed under `module`.
+pub(crate) fn is_package_module(file_names: &[String], module: &str) -> bool {
+    let module = module_ident(module);
+
+    file_names
+        .iter()
+        .filter(|file_name| *file_name != PACKAGELESS_FILE)
+        .any(|file_name| {
+            let package = file_name.trim_end_matches(".rs");
+            module_ident(package.split('.').next().unwrap_or_default()) == module
+        })
+}
+
+/// Converts a protobuf package segment to the module name prost uses when referring to it:
+/// `snake_case`, as a raw identifier if it is a keyword, or suffixed with an underscore for the
+/// keywords that cannot be raw identifiers.
+///
+/// prost already names the generated files after the escaped segments, e.g.
+/// `acme.r#type.foo_bar.rs`, so converting a name twice leaves it unchanged.
+pub fn module_ident(segment: &str) -> String {
+    if segment.starts_with("r#") {
+        return segment.to_string();
+    }
+
+    let mut ident = segment.to_snake_case();
+
+    match ident.as_str() {
+        // 2015 strict keywords.
+        | "as" | "break" | "const" | "continue" | "else" | "enum" | "false"
+        | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut"
+        | "pub" | "ref" | "return" | "static" | "struct" | "trait" | "true"
+        | "type" | "unsafe" | "use" | "where" | "while"
+        // 2018 strict keywords.
+        | "dyn"
+        // 2015 reserved keywords.
+        | "abstract" | "become" | "box" | "do" | "final" | "macro" | "override" | "priv" | "typeof"
+        | "unsized" | "virtual" | "yield"
+        // 2018 reserved keywords.
+        | "async" | "await" | "try" => ident.insert_str(0, "r#"),
+        // Not allowed as raw identifiers.
+        "self" | "super" | "extern" | "crate" => ident.push('_'),
+        _ => (),
+    }
+
+    ident
+}
+
+/// Whether `ident` is a valid module name: an ASCII identifier, optionally raw.
+pub(crate) fn is_module_ident(ident: &str) -> bool {
+    let ident = ident.strip_prefix("r#").unwrap_or(ident);
+    let mut chars = ident.chars();
+    match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || (first == '_' && ident != "_"))
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENERATED_FILES: &[&str] = &[
+        "acme.rs",
+        "acme.billing.rs",
+        "acme.billing.v1.rs",
+        "acme.billing.v1.internal.rs",
+        "acme.common.rs",
+        "google.api.rs",
+        "zeta.rs",
+    ];
+
+    fn paths(tree: &ModuleTree) -> Vec<&str> {
+        tree.files().iter().map(|file| file.path.as_str()).collect()
+    }
+
+    #[test]
+    fn keywords_are_raw_identifiers() {
+        let strict = [
+            "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "false",
+            "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+            "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+            "while",
+        ];
+        let reserved = [
+            "abstract", "become", "box", "do", "final", "macro", "override", "priv", "try",
+            "typeof", "unsized", "virtual", "yield",
+        ];
+
+        for keyword in strict.iter().chain(reserved.iter()) {
+            assert_eq!(module_ident(keyword), format!("r#{}", keyword));
+        }
+    }
+
+    #[test]
+    fn keywords_without_raw_identifiers_are_suffixed() {
+        assert_eq!(module_ident("self"), "self_");
+        assert_eq!(module_ident("Self"), "self_");
+        assert_eq!(module_ident("super"), "super_");
+        assert_eq!(module_ident("crate"), "crate_");
+        assert_eq!(module_ident("extern"), "extern_");
+    }
+
+    #[test]
+    fn segments_are_snake_case() {
+        assert_eq!(module_ident("v1"), "v1");
+        assert_eq!(module_ident("fooBar"), "foo_bar");
+        assert_eq!(module_ident("FooBar"), "foo_bar");
+        assert_eq!(module_ident("Type"), "r#type");
+    }
+
+    #[test]
+    fn escaped_segments_are_unchanged() {
+        for segment in &["acme", "foo_bar", "r#type", "r#async", "self_", "v1"] {
+            assert_eq!(&module_ident(segment), segment);
+        }
+    }
+
+    #[test]
+    fn packages_are_nested_modules() {
+        let tree = ModuleTree::new(GENERATED_FILES, None);
+
+        let acme = &tree.children[0];
+        assert_eq!(acme.name, "acme");
+        assert_eq!(acme.file.as_ref().unwrap().path, "acme.rs");
+
+        let billing = &acme.children[0];
+        assert_eq!(billing.name, "billing");
+        assert_eq!(billing.package, "acme.billing");
+        assert_eq!(billing.children[0].package, "acme.billing.v1");
+        assert_eq!(
+            billing.children[0].children[0].file,
+            Some(GeneratedFile {
+                name: String::from("acme.billing.v1.internal.rs"),
+                path: String::from("acme/billing/v1/acme.billing.v1.internal.rs"),
+            })
+        );
+
+        let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["acme", "google", "zeta"]);
+        assert!(tree.children[1].file.is_none());
+    }
+
+    #[test]
+    fn files_are_laid_out_next_to_their_siblings() {
+        assert_eq!(
+            paths(&ModuleTree::new(GENERATED_FILES, None)),
+            vec![
+                "acme.rs",
+                "acme/acme.billing.rs",
+                "acme/billing/acme.billing.v1.rs",
+                "acme/billing/v1/acme.billing.v1.internal.rs",
+                "acme/acme.common.rs",
+                "google/google.api.rs",
+                "zeta.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn directories_are_named_after_the_modules() {
+        assert_eq!(
+            paths(&ModuleTree::new(
+                &["acme.r#type.v1.rs", "acme.type.v2.rs", "fooBar.v1.rs"],
+                None
+            )),
+            vec![
+                "acme/type/acme.r#type.v1.rs",
+                "acme/type/acme.type.v2.rs",
+                "foo_bar/fooBar.v1.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn tree_does_not_depend_on_the_order_of_the_files() {
+        let expected = ModuleTree::new(GENERATED_FILES, None);
+
+        let mut reversed = GENERATED_FILES.to_vec();
+        reversed.reverse();
+        assert_eq!(ModuleTree::new(&reversed, None), expected);
+
+        // A fixed interleaving, so that neither sorted nor reverse-sorted order is assumed.
+        let mut shuffled = GENERATED_FILES.to_vec();
+        for i in 0..shuffled.len() {
+            shuffled.swap(i, (i * 7 + 3) % GENERATED_FILES.len());
+        }
+        assert_eq!(ModuleTree::new(&shuffled, None), expected);
+    }
+
+    #[test]
+    fn packageless_file_is_placed_according_to_the_configuration() {
+        let hoisted = ModuleTree::new(&["_.rs", "zeta.rs"], Some(&Packageless::Hoist));
+        assert!(hoisted.file.as_ref().unwrap().is_packageless());
+
+        let module = Packageless::Module(String::from("Types"));
+        let nested = ModuleTree::new(&["_.rs", "zeta.rs"], Some(&module));
+        assert!(nested.file.is_none());
+        assert_eq!(nested.children[0].name, "types");
+        assert_eq!(nested.children[0].package, "");
+        assert_eq!(paths(&nested), vec!["_.rs", "zeta.rs"]);
+    }
+
+    #[test]
+    fn packageless_module_must_not_be_a_package() {
+        let file_names: Vec<String> = ["_.rs", "acme.common.rs", "zeta.rs"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        assert!(is_package_module(&file_names, "acme"));
+        assert!(is_package_module(&file_names, "Zeta"));
+        assert!(!is_package_module(&file_names, "common"));
+        assert!(!is_package_module(&file_names, "_"));
+    }
+
+    #[test]
+    fn lay_out_moves_the_files_to_their_path() {
+        let out_dir = tempfile::tempdir().unwrap();
+        for file_name in GENERATED_FILES {
+            fs::write(out_dir.path().join(file_name), file_name).unwrap();
+        }
+
+        let tree = ModuleTree::new(generated_files(out_dir.path()).unwrap(), None);
+        lay_out(&tree, out_dir.path()).unwrap();
+
+        for file in tree.files() {
+            assert_eq!(
+                fs::read_to_string(out_dir.path().join(&file.path)).unwrap(),
+                file.name
+            );
+        }
+    }
+
+    #[test]
+    fn module_idents_are_validated() {
+        for ident in ["types", "_types", "types_2", "r#type"] {
+            assert!(is_module_ident(ident), "{}", ident);
+        }
+        for ident in ["", "_", "1types", "my-types", "my.types", "r#", "types!"] {
+            assert!(!is_module_ident(ident), "{}", ident);
+        }
+    }
+}
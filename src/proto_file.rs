@@ -0,0 +1,133 @@
+//! Just enough parsing of `.proto` sources to find their top-level statements.
+
+/// The package declared by a `.proto` source, if any.
+pub fn package(source: &str) -> Option<String> {
+    statements(source).into_iter().find_map(|statement| {
+        statement
+            .strip_prefix("package")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .map(|rest| rest.trim().to_string())
+    })
+}
+
+/// The files imported by a `.proto` source, as written in its `import` statements.
+pub fn imports(source: &str) -> Vec<String> {
+    statements(source)
+        .into_iter()
+        .filter_map(|statement| {
+            let rest = statement.strip_prefix("import")?;
+            let rest = rest.trim_start();
+            let rest = ["public", "weak"]
+                .iter()
+                .find_map(|modifier| rest.strip_prefix(modifier))
+                .unwrap_or(rest)
+                .trim();
+
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let path = rest[1..].strip_suffix(quote)?;
+            Some(path.to_string())
+        })
+        .collect()
+}
+
+/// The `;`-terminated statements of `source`, with comments removed and whitespace trimmed.
+///
+/// Statements nested in blocks are included too, which does not matter for the keywords
+/// looked for here as they are only valid at the top level.
+fn statements(source: &str) -> Vec<String> {
+    let mut code = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) => {
+                code.push(c);
+                if c == '\\' {
+                    code.extend(chars.next());
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                code.push(c);
+            }
+            (None, '/') if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            (None, '/') if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                code.push(' ');
+            }
+            // Blocks end statements too, e.g. `message A {}` followed by `package b;`.
+            (None, '{') | (None, '}') => code.push(';'),
+            _ => code.push(c),
+        }
+    }
+
+    code.split(';')
+        .map(|statement| statement.trim().to_string())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_package_declaration() {
+        let source = r#"
+            // package commented.out;
+            /* package also.commented; */
+            syntax = "proto3";
+            option go_package = "example.com/package;pkg";
+
+            package acme.billing.v1 ;
+
+            message Invoice { string package = 1; }
+        "#;
+
+        assert_eq!(package(source), Some(String::from("acme.billing.v1")));
+    }
+
+    #[test]
+    fn finds_the_imports() {
+        let source = r#"
+            syntax = "proto3";
+            import "acme/common/money.proto";
+            import public 'acme/common/time.proto';
+            import weak "google/protobuf/any.proto";
+            // import "commented/out.proto";
+            importer = 1;
+        "#;
+
+        assert_eq!(
+            imports(source),
+            vec![
+                "acme/common/money.proto",
+                "acme/common/time.proto",
+                "google/protobuf/any.proto",
+            ]
+        );
+    }
+
+    #[test]
+    fn sources_without_package_have_none() {
+        let source = r#"
+            syntax = "proto3";
+            // package commented.out;
+            message Packaged { string package_name = 1; }
+        "#;
+
+        assert_eq!(package(source), None);
+    }
+}
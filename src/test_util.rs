@@ -0,0 +1,12 @@
+use std::fs;
+use std::path::Path;
+
+/// Writes `files`, given as paths relative to `dir` along with their contents, creating the
+/// directories they are in.
+pub fn write_files(dir: &Path, files: &[(&str, &str)]) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+}
@@ -1,48 +1,108 @@
-use std::ffi::OsStr;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
 use tonic_build::Builder;
 
+/// The version requirement of `tonic-build` in `Cargo.toml`, which the generated code depends
+/// on the matching `tonic` version of.
+pub const TONIC_BUILD_VERSION: &str = "0.6";
+
+/// The version requirement of `prost` in `Cargo.toml`, matching the `prost-build` used by
+/// `tonic-build`, which the generated code depends on.
+pub const PROST_VERSION: &str = "0.9";
+
+/// The exact versions of `tonic-build` and `prost-build` the crate is compiled against, as
+/// resolved in the `Cargo.lock` file of the build, or their version requirements if it was not
+/// found.
+pub fn resolved_versions() -> [(&'static str, &'static str); 2] {
+    [
+        (
+            "tonic-build",
+            resolved_version(
+                option_env!("GRPC_BUILD_LOCKED_TONIC_BUILD"),
+                TONIC_BUILD_VERSION,
+            ),
+        ),
+        (
+            "prost-build",
+            resolved_version(option_env!("GRPC_BUILD_LOCKED_PROST_BUILD"), PROST_VERSION),
+        ),
+    ]
+}
+
+/// The first of the space separated `locked` versions compatible with `requirement`, e.g.
+/// `0.6.2` for `0.6`, or `requirement` itself if there is none.
+fn resolved_version(locked: Option<&'static str>, requirement: &'static str) -> &'static str {
+    locked
+        .unwrap_or_default()
+        .split(' ')
+        .find(|version| {
+            *version == requirement
+                || version
+                    .strip_prefix(requirement)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+        .unwrap_or(requirement)
+}
+
 pub fn compile(
-    input_dir: &str,
-    output_dir: &str,
-    server: bool,
-    client: bool,
-    user_config: impl FnOnce(Builder) -> Builder,
-) -> Result<(), anyhow::Error> {
-    let mut protos = vec![];
-    get_protos(protos.as_mut(), Path::new(input_dir))?;
-
-    let compile_includes: PathBuf = match Path::new(input_dir).parent() {
-        None => PathBuf::from("."),
-        Some(parent) => parent.to_path_buf(),
-    };
-
-    user_config(
-        tonic_build::configure()
-            .out_dir(output_dir)
-            .build_client(client)
-            .build_server(server),
+    protos: &[PathBuf],
+    input_dirs: &[PathBuf],
+    include_dirs: &[PathBuf],
+    output_dir: &Path,
+    builder: Builder,
+) -> io::Result<()> {
+    builder.out_dir(output_dir).compile(
+        protos,
+        compile_includes(input_dirs, include_dirs).as_slice(),
     )
-    .compile(protos.as_slice(), &[compile_includes])?;
-
-    Ok(())
 }
 
-fn get_protos(protos: &mut Vec<PathBuf>, dir: &Path) -> io::Result<()> {
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-
-            if path.is_dir() {
-                get_protos(protos, &path)?;
-            } else if let Some(extension) = path.extension() {
-                if extension == OsStr::new("proto") {
-                    protos.push(path.as_path().to_owned());
-                }
-            }
+/// The include paths passed to protoc: the explicit include directories followed by the
+/// parent of every input directory that is not already covered by one of them.
+pub fn compile_includes(input_dirs: &[PathBuf], include_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut includes = include_dirs.to_vec();
+
+    for input_dir in input_dirs {
+        if include_dirs
+            .iter()
+            .any(|include| input_dir.starts_with(include))
+        {
+            continue;
         }
+
+        let parent = match input_dir.parent() {
+            Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        if !includes.contains(&parent) {
+            includes.push(parent);
+        }
+    }
+
+    includes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_match_the_dependencies() {
+        let manifest = include_str!("../Cargo.toml");
+        assert!(manifest.contains(&format!("\ntonic-build = \"{}\"\n", TONIC_BUILD_VERSION)));
+        assert!(manifest.contains(&format!("\nprost = \"{}\"\n", PROST_VERSION)));
+
+        let [(_, tonic_build), (_, prost_build)] = resolved_versions();
+        assert!(tonic_build.starts_with(TONIC_BUILD_VERSION));
+        assert!(prost_build.starts_with(PROST_VERSION));
+    }
+
+    #[test]
+    fn resolved_versions_match_the_requirement() {
+        assert_eq!(resolved_version(Some("0.5.2 0.6.1"), "0.6"), "0.6.1");
+        assert_eq!(resolved_version(Some("0.60.0"), "0.6"), "0.6");
+        assert_eq!(resolved_version(Some(""), "0.6"), "0.6");
+        assert_eq!(resolved_version(None, "0.6"), "0.6");
     }
-    Ok(())
 }